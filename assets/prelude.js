@@ -0,0 +1,39 @@
+// Prepended to flite.js by g_flite. If the subtask input starts with a
+// `#g_flite ` header line, the JSON options in it are unpacked before flite
+// runs: the header is stripped from the input and the flite args are
+// prepended to the command line. Lexicon addenda, if any, are written to a
+// file of their own, which flite is told to load with `-add_lex`.
+//
+// Emscripten runs the preRun callbacks from the last one in the array to the
+// first, so this one goes first in order to run after those of the host,
+// which set up the file system the input is read from.
+var Module = typeof Module !== "undefined" ? Module : {};
+Module["preRun"] = [].concat(Module["preRun"] || []);
+Module["preRun"].unshift(function () {
+  var header = "#g_flite ";
+  var args = Module["arguments"] || [];
+  if (args.length < 2) {
+    return;
+  }
+  var input = args[args.length - 2];
+  var contents;
+  try {
+    contents = FS.readFile(input, { encoding: "utf8" });
+  } catch (e) {
+    // leave it to flite to report the missing input
+    return;
+  }
+  var newline = contents.indexOf("\n");
+  if (contents.indexOf(header) !== 0 || newline < 0) {
+    return;
+  }
+  var options = JSON.parse(contents.substring(header.length, newline));
+  FS.writeFile(input, contents.substring(newline + 1));
+  var extra = options.args || [];
+  if (options.lexicon) {
+    var lexicon = "/tmp/g_flite_lexicon";
+    FS.writeFile(lexicon, options.lexicon);
+    extra = extra.concat(["-add_lex", lexicon]);
+  }
+  Array.prototype.splice.apply(args, [0, 0].concat(extra));
+});
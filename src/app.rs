@@ -1,13 +1,24 @@
+use super::detached::Submission;
 use super::Opt;
 use anyhow::{anyhow, bail, Context, Result};
 use console::{style, Emoji};
-use gwasm_api::prelude::*;
-use hound;
-use indicatif::ProgressBar;
-use std::cell::Cell;
+use g_flite::audio::SeamOptions;
+use g_flite::backend::{self, Backend, BackendKind, TaskState};
+use g_flite::cache::Cache;
+use g_flite::cancel::CancelToken;
+use g_flite::document::{self, Contents, Document};
+use g_flite::input::InputFormat;
+use g_flite::job::{self, Job};
+use g_flite::lexicon::Lexicon;
+use g_flite::normalize::Normalizer;
+use g_flite::output::OutputFormat;
+use g_flite::plan::{Plan, Pricing};
+use g_flite::{flite, Synthesizer};
+use gwasm_api::prelude::{Net, Timeout};
 use std::convert::TryFrom;
+use std::fmt;
 use std::path::{Path, PathBuf};
-use std::{fmt, fs};
+use std::time::Duration;
 use tempfile::{Builder, TempDir};
 
 static TRUCK: Emoji = Emoji("🚚  ", "");
@@ -15,8 +26,8 @@ static CLIP: Emoji = Emoji("🔗  ", "");
 static PAPER: Emoji = Emoji("📃  ", "");
 static HOURGLASS: Emoji = Emoji("⌛  ", "");
 
-const FLITE_JS: &[u8] = include_bytes!("../assets/flite.js");
-const FLITE_WASM: &[u8] = include_bytes!("../assets/flite.wasm");
+/// Number of subtasks the input is split into unless set otherwise
+const DEFAULT_SUBTASKS: usize = 6;
 
 #[derive(Debug)]
 enum Workspace {
@@ -33,6 +44,16 @@ impl fmt::Display for Workspace {
     }
 }
 
+impl Workspace {
+    /// Consumes the workspace without removing it from disk
+    fn keep(self) -> PathBuf {
+        match self {
+            Workspace::UserSpecified(path) => path,
+            Workspace::Temp(dir) => dir.keep(),
+        }
+    }
+}
+
 impl AsRef<Path> for Workspace {
     fn as_ref(&self) -> &Path {
         match self {
@@ -42,208 +63,432 @@ impl AsRef<Path> for Workspace {
     }
 }
 
-struct ProgressUpdater {
-    bar: ProgressBar,
-    progress: Cell<f64>,
-    num_subtasks: u64,
+/// Settings of the Golem backend, which is only connected to once there is
+/// something to compute
+#[derive(Debug)]
+pub struct GolemSettings {
+    datadir: Option<PathBuf>,
+    address: String,
+    port: u16,
+    net: Net,
+    bid: f64,
+    budget: Option<f64>,
+    task_timeout: Timeout,
+    subtask_timeout: Timeout,
 }
 
-impl ProgressUpdater {
-    fn new(num_subtasks: u64) -> Self {
+impl GolemSettings {
+    pub fn new(opt: &Opt) -> Self {
         Self {
-            bar: ProgressBar::new(num_subtasks),
-            progress: Cell::new(0.0),
-            num_subtasks,
-        }
-    }
-}
-
-impl ProgressUpdate for ProgressUpdater {
-    fn update(&self, progress: f64) {
-        let old_progress = self.progress.get();
-        if progress > old_progress {
-            let delta = progress - old_progress;
-            self.progress.set(progress);
-            self.bar
-                .inc((delta * self.num_subtasks as f64).round() as u64);
+            datadir: opt.datadir.clone(),
+            address: opt.address.clone(),
+            port: opt.port,
+            net: if opt.mainnet {
+                Net::MainNet
+            } else {
+                Net::TestNet
+            },
+            bid: opt.bid,
+            budget: opt.budget,
+            task_timeout: opt.task_timeout,
+            subtask_timeout: opt.subtask_timeout,
         }
     }
 
-    fn start(&self) {
-        self.bar.inc(0)
-    }
+    /// Backend computing on the Golem node, preparing its tasks in
+    /// `workspace`
+    pub fn backend(&self, workspace: &Path) -> Result<backend::Golem> {
+        let datadir = match &self.datadir {
+            Some(datadir) => datadir.canonicalize().with_context(|| {
+                format!(
+                    "working out absolute path for the provided datadir '{}'",
+                    datadir.display(),
+                )
+            })?,
+            None => match appdirs::user_data_dir(Some("golem"), Some("golem"), false) {
+                Ok(datadir) => datadir.join("default"),
+                Err(_) => bail!(
+                    "
+                    No standard project app datadirs available.
+                    You'll need to specify path to your Golem datadir manually.
+                    "
+                ),
+            },
+        };
 
-    fn stop(&self) {
-        self.bar.finish_and_clear()
+        Ok(backend::Golem {
+            datadir,
+            address: self.address.clone(),
+            port: self.port,
+            net: self.net.clone(),
+            bid: self.bid,
+            budget: self.budget,
+            task_timeout: self.task_timeout,
+            subtask_timeout: self.subtask_timeout,
+            workspace: workspace.to_path_buf(),
+        })
     }
 }
 
 #[derive(Debug)]
 pub struct App {
-    input: PathBuf,
+    documents: Vec<Document>,
+    /// Input format set on the command line, overriding the one inferred
+    /// from the extension of each input
+    input_format: Option<InputFormat>,
+    /// Dir the output ends up in
     output_dir: PathBuf,
-    output_filename: PathBuf,
-    datadir: PathBuf,
-    address: String,
-    port: u16,
-    num_subtasks: u64,
-    bid: f64,
-    budget: Option<f64>,
-    task_timeout: Timeout,
-    subtask_timeout: Timeout,
+    output_format: OutputFormat,
     workspace: Workspace,
-    net: Net,
+    /// Synthesizer without a backend, which is only set once there is
+    /// something to compute
+    synth: Synthesizer,
+    backend_kind: BackendKind,
+    golem: GolemSettings,
+    /// Whether to only print the plan of the run instead of computing it
+    dry_run: bool,
+    /// Whether to only print the normalized text instead of computing it
+    print_normalized: bool,
+    /// State of an earlier run, if resuming one
+    resumed: Option<Job>,
+    /// Token the synthesizer checks for cancellation
+    cancel: CancelToken,
 }
 
 impl App {
-    fn split_input(&self) -> Result<Vec<String>> {
-        let contents = fs::read(&self.input)
-            .with_context(|| format!("reading from '{}'", self.input.display()))?;
-        let contents = String::from_utf8(contents).context("converting read bytes to string")?;
-        let word_count = contents.split_whitespace().count();
-
-        if (word_count as u64) < self.num_subtasks {
-            bail!(
-                "splitting input into Golem subtasks: cannot split input of {} words into {} subtasks",
-                word_count, self.num_subtasks
-            );
-        }
+    fn is_batch(&self) -> bool {
+        self.documents.len() > 1
+    }
 
-        log::info!("Input text file has {} words", word_count);
+    /// Token cancelling the subtasks being computed, if any
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
 
-        println!(
-            "{} {}Splitting '{}' into {} Golem subtasks...",
-            style("[1/4]").bold().dim(),
-            PAPER,
-            self.input.to_string_lossy(),
-            self.num_subtasks,
-        );
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
 
-        let mut chunks = Vec::with_capacity(self.num_subtasks as usize);
-        let num_words = (word_count as f64 / self.num_subtasks as f64).ceil() as usize;
+    fn golem_backend(&self) -> Result<backend::Golem> {
+        self.golem.backend(self.workspace.as_ref())
+    }
 
-        log::info!("Each chunk will have max {} words", num_words);
+    fn create_backend(&self) -> Result<Box<dyn Backend>> {
+        Ok(match self.backend_kind {
+            BackendKind::Golem => Box::new(self.golem_backend()?),
+            BackendKind::Local => Box::new(backend::Local {
+                workspace: self.workspace.as_ref().to_path_buf(),
+            }),
+            BackendKind::Mock => Box::new(backend::Mock),
+        })
+    }
 
-        let mut acc = Vec::with_capacity(num_words);
-        for word in contents.split_whitespace() {
-            acc.push(word);
+    /// Splits the documents into a new task recorded in the workspace, or
+    /// picks up the task being resumed
+    fn prepare(&mut self) -> Result<Job> {
+        if let Some(job) = self.resumed.take() {
+            eprintln!(
+                "{} {}Resuming task in '{}' with {} of {} chunks left...",
+                style("[1/4]").bold().dim(),
+                PAPER,
+                self.workspace,
+                job.num_pending(),
+                job.num_chunks(),
+            );
 
-            if acc.len() == num_words {
-                chunks.push(acc);
-                acc = Vec::with_capacity(num_words);
-                continue;
-            }
+            return Ok(job);
         }
 
-        if !acc.is_empty() {
-            chunks.push(acc);
-        }
+        log::info!("Will prepare task in '{}'", self.workspace);
+        let job = Job::create(
+            self.workspace.as_ref(),
+            &self.documents,
+            &self.synth,
+            self.input_format,
+            self.output_format,
+        )?;
 
-        if log::log_enabled!(log::Level::Info) {
-            for (i, chunk) in chunks.iter().enumerate() {
-                log::info!("Chunk {} has {} words", i, chunk.len(),);
-            }
+        if self.is_batch() {
+            eprintln!(
+                "{} {}Splitting {} documents into {} Golem subtasks...",
+                style("[1/4]").bold().dim(),
+                PAPER,
+                self.documents.len(),
+                job.num_chunks(),
+            );
+        } else {
+            eprintln!(
+                "{} {}Splitting {} into {} Golem subtasks...",
+                style("[1/4]").bold().dim(),
+                PAPER,
+                self.documents[0],
+                job.num_chunks(),
+            );
         }
 
-        Ok(chunks.into_iter().map(|chunk| chunk.join(" ")).collect())
+        Ok(job)
     }
 
-    fn prepare_task(&self, chunks: impl IntoIterator<Item = String>) -> Result<Task> {
-        log::info!("Will prepare task in '{}'", self.workspace);
-
-        // prepare Golem task
-        let binary = GWasmBinary {
-            js: FLITE_JS,
-            wasm: FLITE_WASM,
+    /// Takes the chunks of `job` which are not done yet from the cache
+    /// where possible
+    fn restore_cached(&self, job: &mut Job) -> Result<()> {
+        let num_cached = match self.synth.cache() {
+            Some(cache) => job.restore_cached(cache)?,
+            None => 0,
         };
-        // get expected output dir (if any)
-        let mut task_builder = TaskBuilder::new(&self.workspace, binary)
-            .name("g_flite")
-            .bid(self.bid)
-            .timeout(self.task_timeout)
-            .subtask_timeout(self.subtask_timeout)
-            .output_path(&self.output_dir);
-
-        if let Some(budget) = self.budget {
-            task_builder = task_builder.budget(budget);
+        if num_cached > 0 {
+            eprintln!(
+                "{} {}Found {} of {} chunks in the cache...",
+                style("[2/4]").bold().dim(),
+                TRUCK,
+                num_cached,
+                job.num_chunks(),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Computes the chunks of `job` which are not done yet, taking them
+    /// from the cache where possible
+    fn compute(&mut self, job: &mut Job) -> Result<()> {
+        self.restore_cached(job)?;
+        if job.num_pending() == 0 {
+            return Ok(());
         }
 
-        for chunk in chunks {
-            task_builder = task_builder.push_subtask_data(chunk.as_bytes());
+        let backend = self.create_backend()?;
+        eprintln!(
+            "{} {}Sending task to {}...",
+            style("[2/4]").bold().dim(),
+            TRUCK,
+            backend.name(),
+        );
+        self.synth.set_backend(backend);
+
+        eprintln!(
+            "{} {}Waiting on compute to finish...",
+            style("[3/4]").bold().dim(),
+            HOURGLASS
+        );
+
+        let max_retries = self.synth.max_retries();
+        let result = job.compute(&self.synth, |num_failed, attempt| {
+            eprintln!(
+                "{} {}Retrying {} failed chunks ({} of {})...",
+                style("[3/4]").bold().dim(),
+                HOURGLASS,
+                num_failed,
+                attempt,
+                max_retries,
+            )
+        });
+
+        if result.is_err() && !self.is_cancelled() {
+            if let Workspace::UserSpecified(path) = &self.workspace {
+                eprintln!(
+                    "Run again with --resume '{}' to retry the incomplete chunks",
+                    path.display()
+                );
+            }
         }
 
-        task_builder.build().context("building gWasm task")
+        result
     }
 
-    fn combine_output(&self, task: ComputedTask) -> Result<()> {
-        let output = self.output_dir.join(&self.output_filename);
-        println!(
-            "{} {}Combining output into '{}'...",
-            style("[4/4]").bold().dim(),
-            CLIP,
-            output.display()
-        );
+    /// Consumes the app, keeping its workspace on disk even if it is a
+    /// temporary one, and returns its path
+    pub fn keep_workspace(self) -> PathBuf {
+        self.workspace.keep()
+    }
 
-        let mut writer: Option<hound::WavWriter<_>> = None;
+    fn combine(&self, job: &Job) -> Result<()> {
+        let documents = &job.manifest().documents;
+        if self.is_batch() {
+            eprintln!(
+                "{} {}Combining output of {} documents into '{}'...",
+                style("[4/4]").bold().dim(),
+                CLIP,
+                documents.len(),
+                self.output_dir.display()
+            );
+        }
 
-        log::info!("Computed task = {:?}", task);
+        for document in documents {
+            let output = &document.output;
+            if !self.is_batch() {
+                let destination = if document::is_stdio(output) {
+                    "stdout".into()
+                } else {
+                    format!("'{}'", output.display())
+                };
+                eprintln!(
+                    "{} {}Combining output into {}...",
+                    style("[4/4]").bold().dim(),
+                    CLIP,
+                    destination
+                );
+            }
 
-        for (i, subtask) in task.subtasks.into_iter().enumerate() {
-            for (_, reader) in subtask.data.into_iter() {
-                let reader = hound::WavReader::new(reader).context("parsing WAVE input")?;
+            let combined = match job.combine(document)? {
+                Some(combined) => combined,
+                None => continue,
+            };
+
+            if !document.chapters.is_empty() {
+                eprintln!(
+                    "Chapter markers of {} chapters written to '{}'",
+                    document.chapters.len(),
+                    job::cue_path(document).display()
+                );
+            }
 
-                if writer.is_none() {
-                    writer = Some(
-                        hound::WavWriter::create(&output, reader.spec()).with_context(|| {
-                            format!("creating output WAVE file '{}'", output.display())
-                        })?,
+            if job.manifest().output_format == OutputFormat::Pcm {
+                let sample_rate = combined.sample_rate;
+                if self.is_batch() {
+                    eprintln!(
+                        "Raw PCM output '{}' is 16-bit little-endian mono at {} Hz",
+                        output.display(),
+                        sample_rate
+                    );
+                } else {
+                    eprintln!(
+                        "Raw PCM output is 16-bit little-endian mono at {} Hz",
+                        sample_rate
                     );
                 }
+            }
+        }
 
-                let mut wrt = writer.as_mut().unwrap().get_i16_writer(reader.len());
-                for sample in reader.into_samples::<i16>() {
-                    sample
-                        .map(|sample| unsafe { wrt.write_sample_unchecked(sample) })
-                        .with_context(|| format!("reading audio sample from subtask '{}'", i))?;
+        Ok(())
+    }
+
+    /// Prints how the input would be split into subtasks and what computing
+    /// them would cost, without computing anything
+    fn print_plan(&self) -> Result<()> {
+        let mut plan = Plan::new(&self.synth, &self.documents, self.input_format)?;
+        if self.backend_kind == BackendKind::Golem {
+            plan = plan.pricing(Pricing::new(
+                self.golem.bid,
+                self.golem.budget,
+                &self.golem.subtask_timeout,
+            )?);
+        }
+
+        println!("{}", plan);
+        if plan.pricing.is_none() {
+            println!(
+                "Computing on the {} costs nothing",
+                self.backend_kind.name()
+            );
+        }
+        eprintln!("Dry run; nothing was sent to {}", self.backend_kind.name());
+
+        Ok(())
+    }
+
+    /// Prints the text of each document the way it is synthesized, after
+    /// normalization, without computing anything
+    fn print_normalized(&self) -> Result<()> {
+        for (i, document) in self.documents.iter().enumerate() {
+            if self.is_batch() {
+                if i > 0 {
+                    println!();
                 }
-                wrt.flush().with_context(|| {
-                    format!("writing audio samples to file '{}'", output.display(),)
-                })?;
+                println!("Text of {}:", document);
             }
+
+            println!("{}", document.normalize(&self.synth, self.input_format)?);
         }
 
         Ok(())
     }
 
-    pub fn run(&self) -> Result<()> {
-        let chunks = self.split_input()?;
-        let task = self.prepare_task(chunks)?;
+    pub fn run(&mut self) -> Result<()> {
+        if self.print_normalized {
+            return self.print_normalized();
+        }
+        if self.dry_run {
+            return self.print_plan();
+        }
 
-        log::debug!("g_flite run task = {:?}", task);
+        let mut job = self.prepare()?;
+        self.compute(&mut job)?;
+        self.combine(&job)
+    }
+
+    /// Splits the input and creates a Golem task computing the chunks which
+    /// aren't in the cache, leaving the node to compute it; if there is
+    /// nothing left to compute, the output is combined right away instead
+    pub fn submit(&mut self) -> Result<Option<Submission>> {
+        if self.backend_kind != BackendKind::Golem {
+            bail!(
+                "Only tasks computed on Golem can be submitted; run without the submit command to compute on the {}",
+                self.backend_kind.name()
+            );
+        }
 
-        println!(
+        let mut job = self.prepare()?;
+        self.restore_cached(&mut job)?;
+        let chunks = job.manifest().pending();
+        if chunks.is_empty() {
+            self.combine(&job)?;
+            return Ok(None);
+        }
+
+        eprintln!(
             "{} {}Sending task to Golem...",
             style("[2/4]").bold().dim(),
-            TRUCK
+            TRUCK,
         );
+        let task = self
+            .golem_backend()?
+            .create_task(job.manifest().task(&chunks))?;
+
+        Ok(Some(Submission {
+            id: task.id,
+            output_dir: task.output_dir,
+            chunks,
+        }))
+    }
 
-        println!(
-            "{} {}Waiting on compute to finish...",
-            style("[3/4]").bold().dim(),
-            HOURGLASS
-        );
+    /// Takes the outputs of a submitted task from its workspace once the
+    /// node is done with it, and combines them provided all of the chunks
+    /// have been computed
+    pub fn fetch(&mut self, submission: &Submission) -> Result<()> {
+        let id = &submission.id;
+        let state = self.golem_backend()?.task_state(id)?;
+        if let TaskState::Computing(_) = state {
+            bail!("Task '{}' is not finished yet; it is {}", id, state);
+        }
 
-        let progress_updater = ProgressUpdater::new(self.num_subtasks);
-        let computed_task = compute(
-            self.datadir.clone(),
-            self.address.clone(),
-            self.port,
-            self.net.clone(),
-            task,
-            progress_updater,
-        )?;
+        let mut job = self
+            .resumed
+            .take()
+            .ok_or_else(|| anyhow!("No submitted task to fetch"))?;
+        let outputs =
+            backend::Golem::collect_outputs(&submission.output_dir, submission.chunks.len());
+        for (&i, output) in submission.chunks.iter().zip(outputs) {
+            let subtask = &job.manifest().entries[i].subtask;
+            match output.and_then(|output| self.synth.accept_output(subtask, output)) {
+                Ok(output) => job.save_result(i, &output)?,
+                Err(e) => log::warn!("Computing chunk {} failed: {:#}", i, e),
+            }
+        }
+
+        let num_pending = job.num_pending();
+        if num_pending > 0 {
+            bail!(
+                "Task '{}' {} with {} of {} chunks left to compute; run g_flite with --resume '{}' to compute them",
+                id,
+                state,
+                num_pending,
+                job.num_chunks(),
+                self.workspace,
+            );
+        }
 
-        self.combine_output(computed_task)
+        self.combine(&job)
     }
 }
 
@@ -251,68 +496,7 @@ impl TryFrom<Opt> for App {
     type Error = anyhow::Error;
 
     fn try_from(opt: Opt) -> std::result::Result<Self, Self::Error> {
-        // verify input exists
-        let input = opt.input;
-        if !input.is_file() {
-            bail!(
-                "Input file '{}' doesn't exist. Did you make a typo anywhere?",
-                input.display()
-            );
-        }
-
-        // verify output path excluding topmost file exists
-        let output = if opt.output.is_relative() {
-            Path::new(".").join(opt.output)
-        } else {
-            opt.output.into()
-        };
-        let (output_dir, output_filename) = {
-            let parent = output.parent().unwrap(); // guaranteed not to fail
-            let filename = output.file_name().ok_or(anyhow!(
-                "working out the expected output filename from '{}'",
-                output.display()
-            ))?;
-            (parent.to_path_buf(), PathBuf::from(filename))
-        };
-        let output_dir = output_dir.canonicalize().with_context(|| {
-            format!(
-                "working out absolute path for the expected output path '{}'",
-                output.display(),
-            )
-        })?;
-
-        let datadir = match opt.datadir {
-            Some(datadir) => datadir.canonicalize().with_context(|| {
-                format!(
-                    "working out absolute path for the provided datadir '{}'",
-                    datadir.display(),
-                )
-            })?,
-            None => match appdirs::user_data_dir(Some("golem"), Some("golem"), false) {
-                Ok(datadir) => datadir.join("default"),
-                Err(_) => bail!(
-                    "
-                    No standard project app datadirs available.
-                    You'll need to specify path to your Golem datadir manually.
-                    "
-                ),
-            },
-        };
-
-        let address = opt.address;
-        let port = opt.port;
-        let num_subtasks = opt.subtasks;
-        let bid = opt.bid;
-        let budget = opt.budget;
-        let task_timeout = opt.task_timeout;
-        let subtask_timeout = opt.subtask_timeout;
-        let net = if opt.mainnet {
-            Net::MainNet
-        } else {
-            Net::TestNet
-        };
-
-        let workspace = match opt.workspace {
+        let workspace = match opt.resume.as_ref().or(opt.workspace.as_ref()) {
             Some(workspace) => {
                 Workspace::UserSpecified(workspace.canonicalize().with_context(|| {
                     format!(
@@ -328,21 +512,158 @@ impl TryFrom<Opt> for App {
                     .context("creating workspace dir in your tmp files")?,
             ),
         };
+        let resumed = match &opt.resume {
+            Some(_) => Some(Job::resume(workspace.as_ref())?),
+            None => None,
+        };
+
+        let (documents, output_dir) = match &resumed {
+            Some(job) => {
+                let documents = job
+                    .manifest()
+                    .documents
+                    .iter()
+                    .map(|document| Document {
+                        input: document.input.clone(),
+                        output: document.output.clone(),
+                        // the chunks are taken from the manifest instead
+                        contents: Contents::File,
+                    })
+                    .collect();
+                let output_dir = job
+                    .manifest()
+                    .documents
+                    .first()
+                    .and_then(|document| document.output.parent())
+                    .filter(|dir| !dir.as_os_str().is_empty())
+                    .unwrap_or_else(|| Path::new("."))
+                    .to_path_buf();
+                (documents, output_dir)
+            }
+            None => {
+                let input = opt
+                    .input
+                    .clone()
+                    .ok_or_else(|| anyhow!("No input file specified"))?;
+                match &opt.out_dir {
+                    Some(out_dir) => document::batch(&input, out_dir, opt.format)?,
+                    None => {
+                        // the normalized text is printed instead of being
+                        // synthesized, so an output file is optional
+                        let output = match &opt.output {
+                            Some(output) => output.clone(),
+                            None if opt.print_normalized => PathBuf::from(document::STDIO_PATH),
+                            None => bail!("No output file specified"),
+                        };
+                        document::single(input, output, !opt.print_normalized)?
+                    }
+                }
+            }
+        };
+        // when streaming to stdout, the current dir is used
+        let output_dir = output_dir.canonicalize().with_context(|| {
+            format!(
+                "working out absolute path for the expected output dir '{}'",
+                output_dir.display(),
+            )
+        })?;
+        let output_format = match &resumed {
+            Some(job) => job.manifest().output_format,
+            None => opt
+                .format
+                .unwrap_or_else(|| OutputFormat::from_path(&documents[0].output)),
+        };
+
+        let backend_kind = match opt.backend {
+            Some(kind) => kind,
+            None if opt.local => BackendKind::Local,
+            None => BackendKind::Golem,
+        };
+        let golem = GolemSettings::new(&opt);
+        if let Some(voice) = &opt.voice {
+            if !flite::is_voice(voice) {
+                bail!(
+                    "Unknown voice '{}'. Run with --list-voices to see the available voices.",
+                    voice
+                );
+            }
+        }
+        let seams = match &resumed {
+            Some(job) => job.manifest().seams,
+            None => SeamOptions {
+                trim_silence: opt.trim_silence,
+                align_zero_crossings: opt.align_seams,
+                crossfade: opt.crossfade,
+            },
+        };
+        let cancel = CancelToken::new();
+        let mut synth = Synthesizer::builder()
+            .balance(opt.balance)
+            .rate(opt.rate)
+            .pitch(opt.pitch)
+            .volume(opt.volume)
+            .paragraph_pause(opt.paragraph_pause)
+            .chunk_pause(opt.chunk_pause)
+            .seams(seams)
+            .max_retries(opt.max_retries)
+            .show_progress(true)
+            .cancel_token(cancel.clone());
+        if let Some(voice) = &opt.voice {
+            synth = synth.voice(voice.as_str());
+        }
+        if let Some(phrase) = &opt.code_phrase {
+            synth = synth.code_phrase(phrase.as_str());
+        }
+        // the mock backend doesn't synthesize speech, so its output is kept
+        // out of the cache
+        if !opt.no_cache && backend_kind != BackendKind::Mock {
+            let mut cache = Cache::user_default()?;
+            if let Some(cache_size) = opt.cache_size {
+                cache = cache.max_size(cache_size.saturating_mul(1024 * 1024));
+            }
+            synth = synth.cache(cache);
+        }
+        // rules from files come first, so that they can take precedence
+        // over the built-in ones
+        let mut normalizer = Normalizer::default();
+        for path in &opt.rules {
+            normalizer.load_rules(path)?;
+        }
+        if !opt.no_builtin_rules {
+            normalizer.add_builtin_rules();
+        }
+        synth = synth.normalizer(normalizer);
+        let mut lexicon = Lexicon::default();
+        for path in &opt.lexicon {
+            lexicon.load(path)?;
+        }
+        synth = synth.lexicon(lexicon);
+        synth = match (opt.chunk_words, opt.chunk_seconds) {
+            (Some(num_words), _) => synth.chunk_words(num_words),
+            (_, Some(seconds)) => synth.chunk_duration(
+                Duration::try_from_secs_f64(seconds)
+                    .map_err(|_| anyhow!("Invalid chunk length {}s", seconds))?,
+            ),
+            _ => synth.subtasks(opt.subtasks.unwrap_or(DEFAULT_SUBTASKS)),
+        };
+        if let Some(max_subtasks) = opt.max_subtasks {
+            synth = synth.max_subtasks(max_subtasks);
+        }
+        let synth = synth.build()?;
 
         Ok(Self {
-            input,
+            documents,
+            input_format: opt.input_format,
             output_dir,
-            output_filename,
-            datadir,
-            address,
-            port,
-            num_subtasks,
-            bid,
-            budget,
-            task_timeout,
-            subtask_timeout,
+            output_format,
             workspace,
-            net,
+            synth,
+            backend_kind,
+            golem,
+            dry_run: opt.dry_run,
+            print_normalized: opt.print_normalized,
+            resumed,
+            cancel,
         })
     }
 }
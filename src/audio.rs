@@ -0,0 +1,169 @@
+//! Processing of the mono 16-bit samples produced by flite where two
+//! consecutive chunks meet.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Samples with an absolute value at or below this are treated as silence
+const SILENCE_THRESHOLD: i16 = 64;
+/// Amount of silence kept around speech when trimming, so that soft onsets
+/// and releases are not cut off
+const TRIM_MARGIN: Duration = Duration::from_millis(20);
+/// How far from a seam to look for a zero crossing
+const ZERO_CROSSING_WINDOW: Duration = Duration::from_millis(5);
+
+/// Options controlling how the seams between chunks are processed
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct SeamOptions {
+    /// Trim leading and trailing silence flite emits for each chunk
+    pub trim_silence: bool,
+    /// Cut each chunk at the nearest zero crossing to the seam
+    pub align_zero_crossings: bool,
+    /// Length of the crossfade between chunks; zero disables it
+    pub crossfade: Duration,
+}
+
+pub fn num_samples(duration: Duration, sample_rate: u32) -> usize {
+    (duration.as_secs_f64() * f64::from(sample_rate)).round() as usize
+}
+
+fn is_silent(sample: i16) -> bool {
+    sample.checked_abs().is_some_and(|x| x <= SILENCE_THRESHOLD)
+}
+
+fn is_zero_crossing(samples: &[i16], i: usize) -> bool {
+    samples[i] == 0 || (i > 0 && (samples[i - 1] < 0) != (samples[i] < 0))
+}
+
+/// Removes leading and trailing silence, keeping a short margin of it
+pub fn trim_silence(samples: &mut Vec<i16>, sample_rate: u32) {
+    let margin = num_samples(TRIM_MARGIN, sample_rate);
+    let first = match samples.iter().position(|&x| !is_silent(x)) {
+        Some(first) => first,
+        None => {
+            samples.clear();
+            return;
+        }
+    };
+    let last = samples
+        .iter()
+        .rposition(|&x| !is_silent(x))
+        .unwrap_or(first);
+
+    samples.truncate((last + 1 + margin).min(samples.len()));
+    samples.drain(..first.saturating_sub(margin));
+}
+
+/// Drops samples at the start, up to the first zero crossing within reach
+pub fn align_start(samples: &mut Vec<i16>, sample_rate: u32) {
+    let window = num_samples(ZERO_CROSSING_WINDOW, sample_rate).min(samples.len());
+    if let Some(i) = (0..window).find(|&i| is_zero_crossing(samples, i)) {
+        samples.drain(..i);
+    }
+}
+
+/// Drops samples at the end, back to the last zero crossing within reach
+pub fn align_end(samples: &mut Vec<i16>, sample_rate: u32) {
+    let window = num_samples(ZERO_CROSSING_WINDOW, sample_rate).min(samples.len());
+    let len = samples.len();
+    if let Some(i) = (len - window..len)
+        .rev()
+        .find(|&i| is_zero_crossing(samples, i))
+    {
+        samples.truncate(i + 1);
+    }
+}
+
+/// Resamples from `from` Hz to `to` Hz using linear interpolation
+pub fn resample(samples: &[i16], from: u32, to: u32) -> Vec<i16> {
+    if from == to {
+        return samples.to_vec();
+    }
+
+    let len = (samples.len() as u64 * u64::from(to) / u64::from(from)) as usize;
+    let step = f64::from(from) / f64::from(to);
+
+    (0..len)
+        .map(|i| {
+            let position = i as f64 * step;
+            let j = position as usize;
+            let a = f64::from(samples[j]);
+            let b = samples.get(j + 1).map_or(a, |&b| f64::from(b));
+            (a + (b - a) * position.fract()).round() as i16
+        })
+        .collect()
+}
+
+fn gain(i: usize, len: usize) -> f64 {
+    (i as f64 + 0.5) / len as f64
+}
+
+/// Scales the samples by `gain`, clipping those which end up out of range
+pub fn amplify(samples: &mut [i16], gain: f64) {
+    for x in samples {
+        *x = (f64::from(*x) * gain)
+            .round()
+            .clamp(f64::from(i16::MIN), f64::from(i16::MAX)) as i16;
+    }
+}
+
+/// Linearly fades in the first `len` samples
+pub fn fade_in(samples: &mut [i16], len: usize) {
+    let len = len.min(samples.len());
+    for (i, x) in samples[..len].iter_mut().enumerate() {
+        *x = (f64::from(*x) * gain(i, len)) as i16;
+    }
+}
+
+/// Linearly fades out the last `len` samples
+pub fn fade_out(samples: &mut [i16], len: usize) {
+    let len = len.min(samples.len());
+    let start = samples.len() - len;
+    for (i, x) in samples[start..].iter_mut().enumerate() {
+        *x = (f64::from(*x) * gain(len - 1 - i, len)) as i16;
+    }
+}
+
+/// Overlaps the last `len` samples of `prev` with the first `len` samples of
+/// `next`; the mixed samples end up at the start of `next`
+pub fn crossfade(prev: &mut Vec<i16>, next: &mut [i16], len: usize) {
+    let len = len.min(prev.len()).min(next.len());
+    let start = prev.len() - len;
+
+    for (i, (x, y)) in prev[start..].iter().zip(next[..len].iter_mut()).enumerate() {
+        let g = gain(i, len);
+        let mixed = f64::from(*x) * (1.0 - g) + f64::from(*y) * g;
+        *y = mixed
+            .round()
+            .clamp(f64::from(i16::MIN), f64::from(i16::MAX)) as i16;
+    }
+
+    prev.truncate(start);
+}
+
+/// Processes the seam between `prev` and `next`, given the length of the
+/// pause that is going to be inserted in between
+pub fn join(
+    options: &SeamOptions,
+    prev: &mut Vec<i16>,
+    next: &mut Vec<i16>,
+    pause: Duration,
+    sample_rate: u32,
+) {
+    if options.align_zero_crossings {
+        align_end(prev, sample_rate);
+        align_start(next, sample_rate);
+    }
+
+    let fade_len = num_samples(options.crossfade, sample_rate);
+    if fade_len == 0 {
+        return;
+    }
+
+    if pause == Duration::from_secs(0) {
+        crossfade(prev, next, fade_len);
+    } else {
+        fade_out(prev, fade_len);
+        fade_in(next, fade_len);
+    }
+}
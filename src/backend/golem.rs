@@ -0,0 +1,278 @@
+use super::{Backend, Task};
+use crate::cancel::{CancelToken, Cancelled};
+use crate::flite;
+use crate::progress::ProgressUpdater;
+use actix::{System, SystemRunner};
+use anyhow::{anyhow, bail, Context, Result};
+use golem_rpc_api::comp::{AsGolemComp, TaskStatus};
+use gwasm_api::prelude::{GWasmBinary, Net, ProgressUpdate, TaskBuilder, Timeout};
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often the node is asked how far along a task is
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+/// How often cancellation is checked for in between
+const CANCEL_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Computes subtasks as a gWasm task on Golem Network
+///
+/// Each task, including each retry of failed subtasks, is prepared in a
+/// fresh `attempt_<n>` dir inside `<workspace>/golem`, as gWasm expects an
+/// empty workspace. The task is then created on the Golem node, which is
+/// polled until it is done, and asked to abort the task if the run is
+/// cancelled. The output of subtask `i` ends up in `out/subtask_<i>/out`
+/// within the attempt dir, from where the outputs of the subtasks which
+/// finished are taken even if the task as a whole fails.
+#[derive(Debug)]
+pub struct Golem {
+    pub datadir: PathBuf,
+    pub address: String,
+    pub port: u16,
+    pub net: Net,
+    pub bid: f64,
+    pub budget: Option<f64>,
+    pub task_timeout: Timeout,
+    pub subtask_timeout: Timeout,
+    pub workspace: PathBuf,
+}
+
+/// A task created on the Golem node, which computes it on its own
+#[derive(Debug, Clone)]
+pub struct CreatedTask {
+    /// Id of the task on the node
+    pub id: String,
+    /// Dir the outputs of the subtasks end up in; see
+    /// [`Golem::collect_outputs`]
+    pub output_dir: PathBuf,
+}
+
+/// State of a task on the Golem node
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskState {
+    /// Being computed, with this fraction of its subtasks done
+    Computing(f64),
+    Finished,
+    /// Aborted, timed out or never started, as described
+    Failed(String),
+}
+
+impl fmt::Display for TaskState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaskState::Computing(progress) => write!(f, "computing, {:.0}% done", progress * 100.0),
+            TaskState::Finished => write!(f, "finished"),
+            TaskState::Failed(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Connection to the Golem node
+struct Node<E> {
+    sys: SystemRunner,
+    endpoint: E,
+}
+
+impl<E: AsGolemComp> Node<E> {
+    fn create_task(&mut self, task: &gwasm_api::task::Task) -> Result<String> {
+        let spec = serde_json::to_value(task).context("serializing gWasm task")?;
+        let (id, error) = self
+            .sys
+            .block_on(self.endpoint.as_golem_comp().create_task(spec))
+            .map_err(|e| anyhow!("creating Golem task: {}", e))?;
+        if let Some(error) = error {
+            bail!("creating Golem task: {}", error);
+        }
+
+        Ok(id)
+    }
+
+    fn task_state(&mut self, id: &str) -> Result<TaskState> {
+        let info = self
+            .sys
+            .block_on(self.endpoint.as_golem_comp().get_task(id.to_string()))
+            .map_err(|e| anyhow!("querying Golem task '{}': {}", id, e))?
+            .ok_or_else(|| anyhow!("no task '{}' on the Golem node", id))?;
+
+        Ok(match info.status {
+            TaskStatus::Finished => TaskState::Finished,
+            TaskStatus::Aborted => TaskState::Failed("aborted".into()),
+            TaskStatus::Timeout => TaskState::Failed("timed out".into()),
+            TaskStatus::ErrorCreating => TaskState::Failed("failed to be created".into()),
+            _ => TaskState::Computing(info.progress.unwrap_or(0.0)),
+        })
+    }
+
+    fn abort_task(&mut self, id: &str) -> Result<()> {
+        self.sys
+            .block_on(self.endpoint.as_golem_comp().abort_task(id.to_string()))
+            .map_err(|e| anyhow!("aborting Golem task '{}': {}", id, e))
+    }
+}
+
+impl Golem {
+    fn connect(&self) -> Result<Node<impl AsGolemComp>> {
+        let mut sys = System::new("g_flite");
+        let endpoint = sys
+            .block_on(golem_rpc_api::connect_to_app(
+                &self.datadir,
+                Some(self.net.clone()),
+                Some((self.address.as_str(), self.port)),
+            ))
+            .map_err(|e| {
+                anyhow!(
+                    "connecting to Golem node at {}:{}: {}",
+                    self.address,
+                    self.port,
+                    e
+                )
+            })?;
+
+        Ok(Node { sys, endpoint })
+    }
+
+    /// Creates the dir for the next attempt at computing a task
+    fn create_attempt_dir(&self) -> Result<PathBuf> {
+        let golem_dir = self.workspace.join("golem");
+        fs::create_dir_all(&golem_dir)
+            .with_context(|| format!("creating Golem dir '{}'", golem_dir.display()))?;
+
+        let mut attempt = 0;
+        loop {
+            let dir = golem_dir.join(format!("attempt_{}", attempt));
+            match fs::create_dir(&dir) {
+                Ok(()) => return Ok(dir),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => attempt += 1,
+                Err(e) => {
+                    return Err(e)
+                        .with_context(|| format!("creating Golem task dir '{}'", dir.display()))
+                }
+            }
+        }
+    }
+
+    /// Prepares `task` as a gWasm task in a fresh attempt dir
+    fn prepare(&self, task: Task) -> Result<(gwasm_api::task::Task, PathBuf)> {
+        let workspace = self.create_attempt_dir()?;
+        let output_dir = workspace.join("out");
+        log::info!("Will prepare task in '{}'", workspace.display());
+
+        let js = [flite::PRELUDE_JS, flite::FLITE_JS].concat();
+        let binary = GWasmBinary {
+            js: &js,
+            wasm: flite::FLITE_WASM,
+        };
+        let mut task_builder = TaskBuilder::new(&workspace, binary)
+            .name("g_flite")
+            .bid(self.bid)
+            .timeout(self.task_timeout)
+            .subtask_timeout(self.subtask_timeout)
+            .output_path(&output_dir);
+
+        if let Some(budget) = self.budget {
+            task_builder = task_builder.budget(budget);
+        }
+
+        for subtask in task.subtasks {
+            let data =
+                flite::encode_input(&subtask.args, subtask.lexicon.as_deref(), &subtask.text);
+            task_builder = task_builder.push_subtask_data(&data[..]);
+        }
+
+        let task = task_builder.build().context("building gWasm task")?;
+        log::debug!("g_flite gWasm task = {:?}", task);
+
+        Ok((task, output_dir))
+    }
+
+    /// Prepares `task` and creates it on the Golem node without waiting on
+    /// it to be computed
+    pub fn create_task(&self, task: Task) -> Result<CreatedTask> {
+        let (task, output_dir) = self.prepare(task)?;
+        let id = self.connect()?.create_task(&task)?;
+        log::info!("Created Golem task '{}'", id);
+
+        Ok(CreatedTask { id, output_dir })
+    }
+
+    /// Asks the Golem node how far along the task `id` is
+    pub fn task_state(&self, id: &str) -> Result<TaskState> {
+        self.connect()?.task_state(id)
+    }
+
+    /// Takes the outputs of the subtasks which finished from the output dir
+    /// of a task, which may have failed as a whole
+    pub fn collect_outputs(output_dir: &Path, num_subtasks: usize) -> Vec<Result<Vec<u8>>> {
+        (0..num_subtasks)
+            .map(|i| {
+                let path = output_dir.join(format!("subtask_{}", i)).join("out");
+                match fs::read(&path) {
+                    Ok(output) if !output.is_empty() => Ok(output),
+                    _ => Err(anyhow!("subtask '{}' did not finish", i)),
+                }
+            })
+            .collect()
+    }
+}
+
+impl Backend for Golem {
+    fn name(&self) -> &str {
+        "Golem"
+    }
+
+    fn compute(
+        &self,
+        task: Task,
+        progress_handler: ProgressUpdater,
+        cancel: &CancelToken,
+    ) -> Result<Vec<Result<Vec<u8>>>> {
+        let num_subtasks = task.subtasks.len();
+        let (task, output_dir) = self.prepare(task)?;
+
+        let mut node = self.connect()?;
+        let id = node.create_task(&task)?;
+        log::info!("Created Golem task '{}'", id);
+
+        progress_handler.start();
+        let mut last_poll = Instant::now();
+        let state = loop {
+            if cancel.is_cancelled() {
+                progress_handler.abandon();
+                // the task costs nothing more once aborted, but if the node
+                // can't be reached, it is left to run until it times out
+                if let Err(e) = node.abort_task(&id) {
+                    log::warn!("{:#}", e);
+                }
+                return Err(Cancelled.into());
+            }
+
+            thread::sleep(CANCEL_CHECK_INTERVAL);
+            if last_poll.elapsed() < POLL_INTERVAL {
+                continue;
+            }
+            last_poll = Instant::now();
+
+            match node.task_state(&id)? {
+                TaskState::Computing(progress) => progress_handler.update(progress),
+                state => break state,
+            }
+        };
+        progress_handler.stop();
+
+        let outputs = Self::collect_outputs(&output_dir, num_subtasks);
+        if let TaskState::Failed(reason) = state {
+            if outputs.iter().all(Result::is_err) {
+                bail!("Golem task '{}' {}", id, reason);
+            }
+            log::warn!(
+                "Golem task '{}' {}; keeping the outputs of the subtasks which finished",
+                id,
+                reason
+            );
+        }
+
+        Ok(outputs)
+    }
+}
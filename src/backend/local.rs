@@ -0,0 +1,110 @@
+use super::{Backend, Subtask, Task};
+use crate::cancel::{CancelToken, Cancelled};
+use crate::flite;
+use crate::progress::ProgressUpdater;
+use anyhow::{anyhow, Context, Result};
+use gwasm_api::prelude::ProgressUpdate;
+use std::fs;
+use std::path::{Path, PathBuf};
+use wasmer_emscripten::{generate_emscripten_env, run_emscripten_instance, EmscriptenGlobals};
+use wasmer_runtime::{compile, Module};
+
+/// Runs the embedded flite WASM binary in-process, one subtask at a time
+///
+/// Each subtask gets its own `subtask_<n>` dir inside `<workspace>/local`,
+/// mirroring the layout of a gWasm task, so that the inputs and outputs can
+/// be inspected afterwards if the workspace was user-specified.
+#[derive(Debug)]
+pub struct Local {
+    pub workspace: PathBuf,
+}
+
+impl Local {
+    fn run_one(module: &Module, args: &[String], input: &Path, output: &Path) -> Result<()> {
+        let mut globals = EmscriptenGlobals::new(module)
+            .map_err(|e| anyhow!("setting up Emscripten globals: {}", e))?;
+        let import_object = generate_emscripten_env(&mut globals);
+        let mut instance = module
+            .instantiate(&import_object)
+            .map_err(|e| anyhow!("instantiating flite WASM module: {}", e))?;
+
+        let input = input.to_string_lossy();
+        let output = output.to_string_lossy();
+        let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+        args.push(&input);
+        args.push(&output);
+        run_emscripten_instance(
+            module,
+            &mut instance,
+            &mut globals,
+            "flite",
+            args,
+            None,
+            vec![],
+        )
+        .map_err(|e| anyhow!("running flite: {}", e))
+    }
+
+    fn run_subtask(module: &Module, subtask: &Subtask, subtask_dir: &Path) -> Result<Vec<u8>> {
+        fs::create_dir_all(subtask_dir)
+            .with_context(|| format!("creating subtask dir '{}'", subtask_dir.display()))?;
+
+        let input = subtask_dir.join("in");
+        fs::write(&input, subtask.text.as_bytes())
+            .with_context(|| format!("writing subtask input '{}'", input.display()))?;
+
+        let mut args = subtask.args.clone();
+        if let Some(lexicon) = &subtask.lexicon {
+            let path = subtask_dir.join("lexicon");
+            fs::write(&path, lexicon.as_bytes())
+                .with_context(|| format!("writing subtask lexicon '{}'", path.display()))?;
+            args.push(flite::ADD_LEX_ARG.into());
+            args.push(path.to_string_lossy().into_owned());
+        }
+
+        let output = subtask_dir.join("out");
+        Self::run_one(module, &args, &input, &output)?;
+
+        fs::read(&output).with_context(|| format!("reading subtask output '{}'", output.display()))
+    }
+}
+
+impl Backend for Local {
+    fn name(&self) -> &str {
+        "local WASM runtime"
+    }
+
+    fn compute(
+        &self,
+        task: Task,
+        progress_handler: ProgressUpdater,
+        cancel: &CancelToken,
+    ) -> Result<Vec<Result<Vec<u8>>>> {
+        let module = compile(flite::FLITE_WASM)
+            .map_err(|e| anyhow!("compiling flite WASM module: {}", e))?;
+        let workdir = self.workspace.join("local");
+        let num_subtasks = task.subtasks.len();
+        let mut outputs = Vec::with_capacity(num_subtasks);
+
+        progress_handler.start();
+
+        for (i, subtask) in task.subtasks.into_iter().enumerate() {
+            if cancel.is_cancelled() {
+                progress_handler.abandon();
+                return Err(Cancelled.into());
+            }
+
+            let subtask_dir = workdir.join(format!("subtask_{}", i));
+            log::info!("Running flite locally for subtask {}", i);
+            outputs.push(
+                Self::run_subtask(&module, &subtask, &subtask_dir)
+                    .with_context(|| format!("computing subtask '{}' locally", i)),
+            );
+            progress_handler.update((i + 1) as f64 / num_subtasks as f64);
+        }
+
+        progress_handler.stop();
+
+        Ok(outputs)
+    }
+}
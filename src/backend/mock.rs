@@ -0,0 +1,74 @@
+use super::{Backend, Task};
+use crate::cancel::{CancelToken, Cancelled};
+use crate::progress::ProgressUpdater;
+use anyhow::{Context, Result};
+use gwasm_api::prelude::ProgressUpdate;
+use std::io::Cursor;
+
+const SAMPLE_RATE: u32 = 16000;
+const SECONDS_PER_WORD: f64 = 0.35;
+
+/// Produces silent WAVE files without running flite at all
+///
+/// The length of each output is proportional to the number of words in
+/// the subtask, which makes this backend handy for exercising the rest
+/// of the pipeline (chunking, combining, encoding) quickly.
+#[derive(Debug, Default)]
+pub struct Mock;
+
+impl Mock {
+    fn synthesize(text: &str) -> Result<Vec<u8>> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let num_words = text.split_whitespace().count();
+        let num_samples = (num_words as f64 * SECONDS_PER_WORD * SAMPLE_RATE as f64) as usize;
+
+        let mut buffer = Vec::new();
+        let mut writer = hound::WavWriter::new(Cursor::new(&mut buffer), spec)
+            .context("creating mock WAVE output")?;
+        for _ in 0..num_samples {
+            writer
+                .write_sample(0i16)
+                .context("writing mock audio sample")?;
+        }
+        writer.finalize().context("finalizing mock WAVE output")?;
+
+        Ok(buffer)
+    }
+}
+
+impl Backend for Mock {
+    fn name(&self) -> &str {
+        "mock backend"
+    }
+
+    fn compute(
+        &self,
+        task: Task,
+        progress_handler: ProgressUpdater,
+        cancel: &CancelToken,
+    ) -> Result<Vec<Result<Vec<u8>>>> {
+        let num_subtasks = task.subtasks.len();
+
+        progress_handler.start();
+
+        let mut outputs = Vec::with_capacity(num_subtasks);
+        for (i, subtask) in task.subtasks.iter().enumerate() {
+            if cancel.is_cancelled() {
+                progress_handler.abandon();
+                return Err(Cancelled.into());
+            }
+
+            outputs.push(Self::synthesize(&subtask.text));
+            progress_handler.update((i + 1) as f64 / num_subtasks as f64);
+        }
+
+        progress_handler.stop();
+
+        Ok(outputs)
+    }
+}
@@ -0,0 +1,94 @@
+mod golem;
+mod local;
+mod mock;
+
+pub use golem::{CreatedTask, Golem, TaskState};
+pub use local::Local;
+pub use mock::Mock;
+
+use crate::cancel::CancelToken;
+use crate::progress::ProgressUpdater;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// A single invocation of flite on a chunk of input text
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Subtask {
+    pub text: String,
+    /// Extra flite args, passed before the input and output file names
+    pub args: Vec<String>,
+    /// Lexicon addenda flite is run with, see [`crate::lexicon::Lexicon`]
+    // left out when absent, so that it doesn't change the cache keys of
+    // subtasks without one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lexicon: Option<String>,
+    /// Volume the output is scaled to, in percent of the voice's default,
+    /// see [`crate::flite::Options::volume_percent`]
+    // left out when absent, as with the lexicon
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume: Option<u16>,
+}
+
+/// Backend-agnostic description of the work to be computed
+#[derive(Debug, Clone, Default)]
+pub struct Task {
+    pub subtasks: Vec<Subtask>,
+}
+
+/// Place where flite subtasks actually get synthesized
+///
+/// Implementors compute every subtask of a [`Task`] and return the
+/// resulting WAVE files, in the same order as the subtasks. A failure of
+/// a single subtask is reported in its own result, so that the subtasks
+/// which succeeded are not lost; an error of the whole task is only
+/// returned if none of the results could be collected.
+pub trait Backend: fmt::Debug {
+    /// Human-readable name used in progress messages
+    fn name(&self) -> &str;
+
+    /// Computes the task, stopping as soon as it can once `cancel` is
+    /// cancelled, with [`crate::cancel::Cancelled`] as the error
+    fn compute(
+        &self,
+        task: Task,
+        progress_handler: ProgressUpdater,
+        cancel: &CancelToken,
+    ) -> Result<Vec<Result<Vec<u8>>>>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackendKind {
+    Golem,
+    Local,
+    Mock,
+}
+
+impl BackendKind {
+    /// Name of the backend, as [`Backend::name`] gives it, for messages
+    /// shown before the backend is created
+    pub fn name(self) -> &'static str {
+        match self {
+            BackendKind::Golem => "Golem",
+            BackendKind::Local => "local WASM runtime",
+            BackendKind::Mock => "mock backend",
+        }
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "golem" => Ok(BackendKind::Golem),
+            "local" => Ok(BackendKind::Local),
+            "mock" => Ok(BackendKind::Mock),
+            other => bail!(
+                "unknown backend '{}'; expected one of: golem, local, mock",
+                other
+            ),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Content-addressed cache of synthesized chunks.
+//!
+//! Each chunk is stored as a WAVE file named after the hash of everything
+//! that determines its audio: the text, the flite args and the embedded
+//! flite build. Editing a document thus only resynthesizes the chunks whose
+//! text actually changed.
+//!
+//! A cache may be given a maximum size, which it is pruned down to by
+//! removing the chunks used least recently; reading a chunk counts as using
+//! it.
+
+use super::backend::Subtask;
+use super::flite;
+use anyhow::{anyhow, Context, Result};
+use openssl::sha::Sha256;
+use std::ffi::OsStr;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::SystemTime;
+
+/// Bumped whenever the way the key is derived changes
+const CACHE_VERSION: &[u8] = b"g_flite chunk cache v1";
+const CHUNKS_DIR: &str = "chunks";
+const CHUNK_EXTENSION: &str = "wav";
+
+/// Size the cache in the user's cache dir is kept within unless set
+/// otherwise, in bytes
+pub const DEFAULT_MAX_SIZE: u64 = 1024 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub struct Cache {
+    dir: PathBuf,
+    /// Hash of the flite build the chunks are synthesized with
+    flite_hash: [u8; 32],
+    /// Size the cache is pruned down to, in bytes, if it is limited
+    max_size: Option<u64>,
+}
+
+impl Cache {
+    /// Opens the cache in `dir`, creating it if necessary; its size is not
+    /// limited
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("creating cache dir '{}'", dir.display()))?;
+
+        let mut hasher = Sha256::new();
+        hasher.update(flite::FLITE_JS);
+        hasher.update(flite::PRELUDE_JS);
+        hasher.update(flite::FLITE_WASM);
+
+        Ok(Self {
+            dir,
+            flite_hash: hasher.finish(),
+            max_size: None,
+        })
+    }
+
+    /// Opens the cache in the user's cache dir, limited to
+    /// [`DEFAULT_MAX_SIZE`]
+    pub fn user_default() -> Result<Self> {
+        let dir = appdirs::user_cache_dir(Some("g_flite"), None)
+            .map_err(|_| anyhow!("No standard project app cache dirs available"))?;
+
+        Ok(Self::new(dir.join(CHUNKS_DIR))?.max_size(DEFAULT_MAX_SIZE))
+    }
+
+    /// Limits the size of the cache to `max_size` bytes, see
+    /// [`Cache::prune`]
+    pub fn max_size(mut self, max_size: u64) -> Self {
+        self.max_size = Some(max_size);
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn key(&self, subtask: &Subtask) -> Result<String> {
+        let mut hasher = Sha256::new();
+        hasher.update(CACHE_VERSION);
+        hasher.update(&self.flite_hash);
+        hasher.update(&serde_json::to_vec(subtask).context("serializing subtask")?);
+
+        let mut key = String::with_capacity(64);
+        for byte in hasher.finish().iter() {
+            write!(key, "{:02x}", byte).unwrap();
+        }
+
+        Ok(key)
+    }
+
+    fn path(&self, subtask: &Subtask) -> Result<PathBuf> {
+        Ok(self
+            .dir
+            .join(self.key(subtask)?)
+            .with_extension(CHUNK_EXTENSION))
+    }
+
+    /// Looks up the audio of `subtask`, if it has been synthesized before
+    pub fn get(&self, subtask: &Subtask) -> Result<Option<Vec<u8>>> {
+        let path = self.path(subtask)?;
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let output = fs::read(&path)
+            .with_context(|| format!("reading cached chunk '{}'", path.display()))?;
+        // the chunk is now the most recently used one, to be pruned last
+        if let Err(e) = File::options()
+            .append(true)
+            .open(&path)
+            .and_then(|file| file.set_modified(SystemTime::now()))
+        {
+            log::debug!("Touching cached chunk '{}' failed: {}", path.display(), e);
+        }
+
+        Ok(Some(output))
+    }
+
+    /// Checks whether the audio of `subtask` has been synthesized before
+    pub fn contains(&self, subtask: &Subtask) -> Result<bool> {
+        Ok(self.path(subtask)?.is_file())
+    }
+
+    /// Stores the audio of `subtask`
+    pub fn put(&self, subtask: &Subtask, output: &[u8]) -> Result<()> {
+        let path = self.path(subtask)?;
+
+        // write to a temporary file first, so that a concurrent run never
+        // reads a partially written chunk
+        let tmp = path.with_extension(format!("{}.tmp", process::id()));
+        fs::write(&tmp, output)
+            .and_then(|_| fs::rename(&tmp, &path))
+            .with_context(|| format!("writing cached chunk '{}'", path.display()))
+    }
+
+    /// Removes the chunks used least recently until the cache fits within
+    /// its maximum size, if it has one; returns how many were removed
+    pub fn prune(&self) -> Result<usize> {
+        let max_size = match self.max_size {
+            Some(max_size) => max_size,
+            None => return Ok(0),
+        };
+
+        let mut chunks = Vec::new();
+        let entries = fs::read_dir(&self.dir)
+            .with_context(|| format!("listing cache dir '{}'", self.dir.display()))?;
+        for entry in entries {
+            let path = entry.context("listing cached chunks")?.path();
+            if path.extension() != Some(OsStr::new(CHUNK_EXTENSION)) {
+                continue;
+            }
+            // a chunk removed by a concurrent run in the meantime is skipped
+            if let Ok(metadata) = path.metadata() {
+                let used = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                chunks.push((used, metadata.len(), path));
+            }
+        }
+
+        let mut size: u64 = chunks.iter().map(|(_, len, _)| len).sum();
+        if size <= max_size {
+            return Ok(0);
+        }
+
+        chunks.sort();
+        let mut num_removed = 0;
+        for (_, len, path) in chunks {
+            if size <= max_size {
+                break;
+            }
+            fs::remove_file(&path)
+                .with_context(|| format!("removing cached chunk '{}'", path.display()))?;
+            size -= len;
+            num_removed += 1;
+        }
+        log::info!("Pruned {} chunks from the cache", num_removed);
+
+        Ok(num_removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn subtask(text: &str) -> Subtask {
+        Subtask {
+            text: text.to_string(),
+            args: Vec::new(),
+            lexicon: None,
+            volume: None,
+        }
+    }
+
+    #[test]
+    fn stores_and_finds_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path()).unwrap();
+
+        assert_eq!(cache.get(&subtask("Hello.")).unwrap(), None);
+        cache.put(&subtask("Hello."), b"RIFF").unwrap();
+        assert_eq!(
+            cache.get(&subtask("Hello.")).unwrap(),
+            Some(b"RIFF".to_vec())
+        );
+        assert!(!cache.contains(&subtask("Hello there.")).unwrap());
+    }
+
+    #[test]
+    fn prunes_least_recently_used_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path()).unwrap().max_size(20);
+        let old = SystemTime::now() - Duration::from_secs(3600);
+
+        for (i, text) in ["One.", "Two.", "Three."].iter().enumerate() {
+            cache.put(&subtask(text), &[0; 10]).unwrap();
+            let path = cache.path(&subtask(text)).unwrap();
+            let used = old + Duration::from_secs(i as u64);
+            File::options()
+                .append(true)
+                .open(&path)
+                .and_then(|file| file.set_modified(used))
+                .unwrap();
+        }
+        // reading the oldest chunk makes it the most recently used one
+        cache.get(&subtask("One.")).unwrap();
+
+        assert_eq!(cache.prune().unwrap(), 1);
+        assert!(cache.contains(&subtask("One.")).unwrap());
+        assert!(!cache.contains(&subtask("Two.")).unwrap());
+        assert!(cache.contains(&subtask("Three.")).unwrap());
+        assert_eq!(cache.prune().unwrap(), 0);
+    }
+}
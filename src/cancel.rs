@@ -0,0 +1,102 @@
+//! Cancellation of a run.
+//!
+//! Cancelling is opt-in: a [`CancelToken`] is handed to the synthesizer with
+//! [`crate::SynthesizerBuilder::cancel_token`], and whoever holds a clone of
+//! it, such as a Ctrl-C handler installed by the host, may cancel the run.
+//! The backends and the rest of the pipeline check the token at points where
+//! they can stop cleanly, keeping the chunks done so far; a task computed on
+//! Golem is aborted on the node.
+
+use anyhow::Result;
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Error returned once the run has been cancelled
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cancelled by user")
+    }
+}
+
+impl Error for Cancelled {}
+
+#[derive(Debug, Default)]
+struct State {
+    cancelled: AtomicBool,
+    in_flight: AtomicBool,
+}
+
+/// Shared flag cancelling the run computing subtasks, if there is one
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<State>);
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the run in flight; returns whether there was one which
+    /// hadn't been cancelled yet
+    pub fn cancel(&self) -> bool {
+        self.0.in_flight.load(Ordering::SeqCst) && !self.0.cancelled.swap(true, Ordering::SeqCst)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Fails with [`Cancelled`] if the run has been cancelled
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+
+        Ok(())
+    }
+
+    /// Marks a run as in flight until the returned guard is dropped,
+    /// clearing any cancellation of an earlier run
+    pub fn guard(&self) -> Guard {
+        self.0.cancelled.store(false, Ordering::SeqCst);
+        self.0.in_flight.store(true, Ordering::SeqCst);
+        Guard(self.clone())
+    }
+}
+
+/// Keeps a run in flight, so that it can be cancelled, until it is dropped
+#[derive(Debug)]
+pub struct Guard(CancelToken);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        (self.0).0.in_flight.store(false, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_cancels_runs_in_flight() {
+        let token = CancelToken::new();
+        assert!(!token.cancel());
+        assert!(!token.is_cancelled());
+
+        let guard = token.guard();
+        assert!(token.clone().cancel());
+        assert!(!token.cancel());
+        assert!(token.check().is_err());
+        drop(guard);
+        assert!(!token.cancel());
+
+        // a new run starts out uncancelled
+        let _guard = token.guard();
+        assert!(token.check().is_ok());
+    }
+}
@@ -0,0 +1,174 @@
+//! Tasks submitted to the Golem node without waiting on them, so that
+//! submitting a task and fetching its output needn't happen within a single,
+//! blocking invocation.
+//!
+//! `submit` splits the input in a workspace dir as a regular run does, then
+//! creates a task computing the chunks on the node and prints the id the
+//! node gave it. From then on the node computes the task on its own,
+//! putting the outputs in the workspace: `status` asks the node how far
+//! along the task is, and `fetch` takes the outputs once the node is done
+//! with it and combines them. The workspace of each task is found through a
+//! file named after its id in the user's data dir.
+
+use super::app::{App, GolemSettings};
+use super::Opt;
+use anyhow::{anyhow, bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Dir within the user's data dir holding the workspaces of submitted tasks
+/// which weren't given one
+const WORKSPACES_DIR: &str = "workspaces";
+/// Dir within the user's data dir holding the path of the workspace of each
+/// submitted task, in a file named after its id
+const TASKS_DIR: &str = "tasks";
+const SUBMISSION_FILENAME: &str = "submission.json";
+
+/// Golem task computing chunks of the task in a workspace
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Submission {
+    /// Id of the task on the Golem node
+    pub id: String,
+    /// Dir the node puts the outputs of the subtasks in
+    pub output_dir: PathBuf,
+    /// Chunks the subtasks compute, in the same order
+    pub chunks: Vec<usize>,
+}
+
+fn data_dir() -> Result<PathBuf> {
+    appdirs::user_data_dir(Some("g_flite"), None, false)
+        .map_err(|_| anyhow!("No standard project app datadirs available to keep tasks in"))
+}
+
+/// Writes `contents` to `path` through a temporary file, so that the file is
+/// never seen half-written
+fn write_atomically(path: &Path, contents: &[u8]) -> Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    fs::write(&tmp, contents)
+        .and_then(|_| fs::rename(&tmp, path))
+        .with_context(|| format!("writing '{}'", path.display()))
+}
+
+fn create_workspace() -> Result<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("reading system time")?;
+    let workspace = data_dir()?
+        .join(WORKSPACES_DIR)
+        .join(format!("{:x}", timestamp.as_millis()));
+    fs::create_dir_all(&workspace)
+        .with_context(|| format!("creating task dir '{}'", workspace.display()))?;
+
+    Ok(workspace)
+}
+
+/// Records the task submitted from `workspace`, so that it can be found by
+/// its id
+fn save_submission(workspace: &Path, submission: &Submission) -> Result<()> {
+    let contents = serde_json::to_vec(submission).context("serializing submitted task")?;
+    write_atomically(&workspace.join(SUBMISSION_FILENAME), &contents)?;
+
+    let tasks_dir = data_dir()?.join(TASKS_DIR);
+    fs::create_dir_all(&tasks_dir)
+        .with_context(|| format!("creating tasks dir '{}'", tasks_dir.display()))?;
+    let workspace = workspace
+        .to_str()
+        .ok_or_else(|| anyhow!("workspace path '{}' is not UTF-8", workspace.display()))?;
+    write_atomically(&tasks_dir.join(&submission.id), workspace.as_bytes())
+}
+
+/// Works out the workspace of the task with the given id
+fn workspace(id: &str) -> Result<PathBuf> {
+    let path = data_dir()?.join(TASKS_DIR).join(id);
+    if !path.is_file() {
+        bail!("Unknown task '{}'; was it submitted from this machine?", id);
+    }
+
+    fs::read_to_string(&path)
+        .map(PathBuf::from)
+        .with_context(|| format!("reading workspace of task '{}'", id))
+}
+
+fn load_submission(workspace: &Path) -> Result<Submission> {
+    let path = workspace.join(SUBMISSION_FILENAME);
+    let contents =
+        fs::read(&path).with_context(|| format!("reading submitted task '{}'", path.display()))?;
+    serde_json::from_slice(&contents)
+        .with_context(|| format!("parsing submitted task '{}'", path.display()))
+}
+
+/// Splits the input and creates a task computing it on the Golem node,
+/// printing the id of the task
+pub fn submit(mut opt: Opt) -> Result<()> {
+    if opt.dry_run || opt.print_normalized {
+        return App::try_from(opt)?.run();
+    }
+
+    let created_workspace = match &opt.workspace {
+        Some(_) => None,
+        None => Some(create_workspace()?),
+    };
+    if let Some(workspace) = &created_workspace {
+        opt.workspace = Some(workspace.clone());
+    }
+
+    let submitted = App::try_from(opt).and_then(|mut app| {
+        Ok(app
+            .submit()?
+            .map(|submission| (submission, app.keep_workspace())))
+    });
+    let (submission, workspace) = match submitted {
+        Ok(Some(submitted)) => submitted,
+        result => {
+            // no task was left on the node to need it
+            if let Some(workspace) = created_workspace {
+                let _ = fs::remove_dir_all(workspace);
+            }
+            result?;
+            eprintln!("All of the chunks were found in the cache; nothing was sent to Golem");
+            return Ok(());
+        }
+    };
+    save_submission(&workspace, &submission)?;
+
+    let id = &submission.id;
+    eprintln!(
+        "Task submitted; check on it with `g_flite status {}` and collect the output with `g_flite fetch {}`",
+        id, id
+    );
+    println!("{}", id);
+
+    Ok(())
+}
+
+/// Asks the Golem node how far along the task is
+pub fn status(id: &str, opt: &Opt) -> Result<()> {
+    let workspace = workspace(id)?;
+    let state = GolemSettings::new(opt)
+        .backend(&workspace)?
+        .task_state(id)?;
+    println!("Task '{}': {}", id, state);
+
+    Ok(())
+}
+
+/// Combines the output of a task once the Golem node is done with it
+pub fn fetch(id: &str, opt: Opt) -> Result<()> {
+    let workspace = workspace(id)?;
+    let submission = load_submission(&workspace)?;
+    // the chunks and the output paths come from the manifest instead
+    let opt = Opt {
+        input: None,
+        output: None,
+        out_dir: None,
+        workspace: None,
+        resume: Some(workspace),
+        ..opt
+    };
+
+    App::try_from(opt)?.fetch(&submission)
+}
@@ -0,0 +1,418 @@
+//! Documents to synthesize, each read from an input file, or a chapter of
+//! one, and written to an output file of its own.
+
+use super::epub::{self, Book};
+use super::input::InputFormat;
+use super::manifest::Chapter;
+use super::output::OutputFormat;
+use super::split::Chunk;
+use super::Synthesizer;
+use anyhow::{anyhow, bail, Context, Result};
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Path standing for stdin when given as the input, and for stdout when
+/// given as the output
+pub const STDIO_PATH: &str = "-";
+
+/// Longest chapter title put in the filename of its output
+const MAX_TITLE_LEN: usize = 80;
+
+pub fn is_stdio(path: &Path) -> bool {
+    path == Path::new(STDIO_PATH)
+}
+
+/// What of the input file makes up a document
+#[derive(Debug)]
+pub enum Contents {
+    /// The whole file
+    File,
+    /// A single chapter of an EPUB book
+    Chapter(epub::Chapter),
+    /// All chapters of an EPUB book, marked in the output
+    Book(Book),
+}
+
+/// Input document together with the path its audio is written to
+#[derive(Debug)]
+pub struct Document {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub contents: Contents,
+}
+
+impl fmt::Display for Document {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.contents {
+            Contents::File if is_stdio(&self.input) => write!(f, "stdin"),
+            Contents::File => write!(f, "'{}'", self.input.display()),
+            Contents::Chapter(chapter) => write!(
+                f,
+                "chapter '{}' of '{}'",
+                chapter.title,
+                self.input.display()
+            ),
+            Contents::Book(book) => write!(
+                f,
+                "{} chapters of '{}'",
+                book.chapters.len(),
+                self.input.display()
+            ),
+        }
+    }
+}
+
+/// Reads the input file, or stdin, also returning a description of where
+/// it was read from
+fn read_input(input: &Path) -> Result<(String, String)> {
+    let (contents, source) = if is_stdio(input) {
+        let mut contents = Vec::new();
+        io::stdin()
+            .read_to_end(&mut contents)
+            .context("reading from stdin")?;
+        (contents, "stdin".into())
+    } else {
+        let contents =
+            fs::read(input).with_context(|| format!("reading from '{}'", input.display()))?;
+        (contents, format!("'{}'", input.display()))
+    };
+    let contents = String::from_utf8(contents).context("converting read bytes to string")?;
+
+    Ok((contents, source))
+}
+
+impl Document {
+    /// Title of the book the document is made of, if it is one
+    pub fn title(&self) -> Option<&str> {
+        match &self.contents {
+            Contents::Book(book) => book.title.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Splits the document into chunks, also working out where its
+    /// chapters start if it is a book; `input_format` overrides the format
+    /// inferred from the extension of a whole input file
+    pub fn split(
+        &self,
+        synth: &Synthesizer,
+        input_format: Option<InputFormat>,
+    ) -> Result<(Vec<Chunk>, Vec<Chapter>)> {
+        match &self.contents {
+            Contents::File => {
+                let (contents, source) = read_input(&self.input)?;
+                let format = input_format.unwrap_or_else(|| InputFormat::from_path(&self.input));
+                let chunks = synth
+                    .split_as(&contents, format)
+                    .with_context(|| format!("splitting input {}", source))?;
+                Ok((chunks, Vec::new()))
+            }
+            Contents::Chapter(chapter) => {
+                let chunks = synth
+                    .split_as(&chapter.contents, InputFormat::Html)
+                    .with_context(|| format!("splitting {}", self))?;
+                Ok((chunks, Vec::new()))
+            }
+            Contents::Book(book) => {
+                let texts: Vec<&str> = book
+                    .chapters
+                    .iter()
+                    .map(|chapter| chapter.contents.as_str())
+                    .collect();
+                let parts = synth
+                    .split_parts(&texts, InputFormat::Html)
+                    .with_context(|| format!("splitting '{}'", self.input.display()))?;
+
+                let mut chunks = Vec::new();
+                let mut chapters = Vec::new();
+                for (chapter, part) in book.chapters.iter().zip(parts) {
+                    chapters.push(Chapter {
+                        title: chapter.title.clone(),
+                        start: chunks.len(),
+                    });
+                    chunks.extend(part);
+                }
+                Ok((chunks, chapters))
+            }
+        }
+    }
+
+    /// Works out the text of the document the way it is synthesized, after
+    /// normalization; see [`Synthesizer::normalize_as`]
+    pub fn normalize(
+        &self,
+        synth: &Synthesizer,
+        input_format: Option<InputFormat>,
+    ) -> Result<String> {
+        match &self.contents {
+            Contents::File => {
+                let (contents, source) = read_input(&self.input)?;
+                let format = input_format.unwrap_or_else(|| InputFormat::from_path(&self.input));
+                synth
+                    .normalize_as(&contents, format)
+                    .with_context(|| format!("normalizing input {}", source))
+            }
+            Contents::Chapter(chapter) => synth
+                .normalize_as(&chapter.contents, InputFormat::Html)
+                .with_context(|| format!("normalizing {}", self)),
+            Contents::Book(book) => Ok(book
+                .chapters
+                .iter()
+                .map(|chapter| {
+                    synth
+                        .normalize_as(&chapter.contents, InputFormat::Html)
+                        .with_context(|| {
+                            format!(
+                                "normalizing chapter '{}' of '{}'",
+                                chapter.title,
+                                self.input.display()
+                            )
+                        })
+                })
+                .collect::<Result<Vec<_>>>()?
+                .join("\n\n")),
+        }
+    }
+}
+
+/// Makes a chapter title safe to use in a filename
+fn sanitize_filename(title: &str) -> String {
+    let filename: String = title
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .take(MAX_TITLE_LEN)
+        .collect();
+
+    filename.trim().trim_start_matches('.').to_string()
+}
+
+/// Lists the documents in `input`, which is either a dir, all of whose
+/// files are taken, or a glob pattern
+fn find(input: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths = if input.is_dir() {
+        fs::read_dir(input)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .with_context(|| format!("listing input dir '{}'", input.display()))?
+    } else {
+        let pattern = input
+            .to_str()
+            .ok_or_else(|| anyhow!("Input pattern '{}' is not valid UTF-8", input.display()))?;
+        glob::glob(pattern)
+            .with_context(|| format!("parsing input pattern '{}'", pattern))?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .with_context(|| format!("listing inputs matching '{}'", pattern))?
+    };
+    paths.retain(|path| path.is_file());
+    paths.sort();
+
+    if paths.is_empty() {
+        bail!(
+            "No input files found in '{}'. Did you make a typo anywhere?",
+            input.display()
+        );
+    }
+
+    Ok(paths)
+}
+
+/// Works out the document of a regular run, along with the dir its output
+/// is written to; `marks_chapters` tells whether the chapters of a book are
+/// going to be marked in its output
+pub fn single(
+    input: PathBuf,
+    output: PathBuf,
+    marks_chapters: bool,
+) -> Result<(Vec<Document>, PathBuf)> {
+    if !is_stdio(&input) && !input.is_file() {
+        bail!(
+            "Input file '{}' doesn't exist. Did you make a typo anywhere?",
+            input.display()
+        );
+    }
+
+    let contents = if epub::is_epub(&input) {
+        if marks_chapters && is_stdio(&output) {
+            bail!(
+                "The chapter markers of an EPUB book can't be written to stdout; write the output to a file, or one file per chapter with --out-dir"
+            );
+        }
+        Contents::Book(epub::read(&input)?)
+    } else {
+        Contents::File
+    };
+
+    if is_stdio(&output) {
+        return Ok((
+            vec![Document {
+                input,
+                output,
+                contents,
+            }],
+            PathBuf::from("."),
+        ));
+    }
+
+    // verify output path excluding topmost file exists
+    let output = if output.is_relative() {
+        Path::new(".").join(output)
+    } else {
+        output
+    };
+    let parent = output.parent().unwrap(); // guaranteed not to fail
+    let filename = output.file_name().ok_or(anyhow!(
+        "working out the expected output filename from '{}'",
+        output.display()
+    ))?;
+    let output_dir = parent.canonicalize().with_context(|| {
+        format!(
+            "working out absolute path for the expected output path '{}'",
+            output.display(),
+        )
+    })?;
+    let output = output_dir.join(filename);
+
+    Ok((
+        vec![Document {
+            input,
+            output,
+            contents,
+        }],
+        output_dir,
+    ))
+}
+
+/// Works out a document for each chapter of an EPUB book, named after the
+/// book, the number of the chapter and its title
+fn chapters(input: &Path, stem: &OsStr, extension: &str) -> Result<Vec<Document>> {
+    let book = epub::read(input)?;
+    let width = book.chapters.len().to_string().len().max(2);
+
+    Ok(book
+        .chapters
+        .into_iter()
+        .enumerate()
+        .map(|(i, chapter)| {
+            let mut filename = stem.to_os_string();
+            filename.push(format!(" {:0width$}", i + 1, width = width));
+            let title = sanitize_filename(&chapter.title);
+            if !title.is_empty() {
+                filename.push(" ");
+                filename.push(title);
+            }
+            filename.push(".");
+            filename.push(extension);
+
+            Document {
+                input: input.to_path_buf(),
+                output: PathBuf::from(filename),
+                contents: Contents::Chapter(chapter),
+            }
+        })
+        .collect())
+}
+
+/// Works out the documents of a batch run, each written to `out_dir` under
+/// the name of its input file; EPUB books are written one file per chapter
+pub fn batch(
+    input: &Path,
+    out_dir: &Path,
+    format: Option<OutputFormat>,
+) -> Result<(Vec<Document>, PathBuf)> {
+    if is_stdio(input) {
+        bail!("Batch input can't be read from stdin; specify a dir or a glob pattern instead");
+    }
+
+    fs::create_dir_all(out_dir)
+        .with_context(|| format!("creating output dir '{}'", out_dir.display()))?;
+    let out_dir = out_dir.canonicalize().with_context(|| {
+        format!(
+            "working out absolute path for the output dir '{}'",
+            out_dir.display(),
+        )
+    })?;
+    let extension = format.unwrap_or(OutputFormat::Wav).extension();
+
+    let mut documents: Vec<Document> = Vec::new();
+    for input in find(input)? {
+        let stem = input.file_stem().ok_or_else(|| {
+            anyhow!(
+                "working out the output filename for input '{}'",
+                input.display()
+            )
+        })?;
+        let input_documents = if epub::is_epub(&input) {
+            chapters(&input, stem, extension)?
+        } else {
+            let mut filename = stem.to_os_string();
+            filename.push(".");
+            filename.push(extension);
+            vec![Document {
+                input: input.clone(),
+                output: PathBuf::from(filename),
+                contents: Contents::File,
+            }]
+        };
+
+        for mut document in input_documents {
+            document.output = out_dir.join(&document.output);
+            if let Some(other) = documents
+                .iter()
+                .find(|other| other.output == document.output)
+            {
+                bail!(
+                    "Inputs '{}' and '{}' would both be written to '{}'",
+                    other.input.display(),
+                    document.input.display(),
+                    document.output.display()
+                );
+            }
+            documents.push(document);
+        }
+    }
+
+    Ok((documents, out_dir))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitizes_chapter_titles() {
+        assert_eq!(
+            sanitize_filename("Part 1: The Beginning"),
+            "Part 1_ The Beginning"
+        );
+        assert_eq!(sanitize_filename("..hidden/title "), "hidden_title");
+        assert_eq!(sanitize_filename(&"x".repeat(200)).len(), MAX_TITLE_LEN);
+    }
+
+    #[test]
+    fn splits_without_a_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("notes.md");
+        fs::write(&input, "# Title\n\nSome text here. And some more.\n").unwrap();
+        let document = Document {
+            input,
+            output: dir.path().join("notes.wav"),
+            contents: Contents::File,
+        };
+        let synth = Synthesizer::builder().subtasks(2).build().unwrap();
+
+        let (chunks, chapters) = document.split(&synth, None).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert!(chapters.is_empty());
+        assert!(!document.normalize(&synth, None).unwrap().contains('#'));
+    }
+}
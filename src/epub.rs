@@ -0,0 +1,341 @@
+//! Reading of EPUB books.
+//!
+//! An EPUB is a zip archive of XHTML documents, whose package document
+//! lists them in reading order, the spine. Each document in the spine with
+//! any text to read makes up a chapter, titled after its first heading;
+//! cover pages and the like, with no text at all, are left out.
+
+use super::html;
+use anyhow::{anyhow, bail, Context, Result};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+use zip::ZipArchive;
+
+const CONTAINER_PATH: &str = "META-INF/container.xml";
+
+#[derive(Debug, Clone)]
+pub struct Book {
+    pub title: Option<String>,
+    pub chapters: Vec<Chapter>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    pub title: String,
+    /// XHTML document of the chapter, to be read as HTML input
+    pub contents: String,
+}
+
+/// Checks whether `path` names an EPUB book, going by its extension
+pub fn is_epub(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("epub"))
+}
+
+fn attribute(element: &BytesStart, name: &[u8]) -> Result<Option<String>> {
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| anyhow!("parsing EPUB attribute: {}", e))?;
+        if attribute.key == name {
+            let value = attribute
+                .unescaped_value()
+                .map_err(|e| anyhow!("unescaping EPUB attribute: {}", e))?;
+            return Ok(Some(String::from_utf8_lossy(&value).into_owned()));
+        }
+    }
+
+    Ok(None)
+}
+
+fn read_entry(archive: &mut ZipArchive<BufReader<File>>, name: &str) -> Result<String> {
+    let mut contents = String::new();
+    archive
+        .by_name(name)
+        .map_err(|e| anyhow!("{}", e))
+        .and_then(|mut entry| entry.read_to_string(&mut contents).map_err(Into::into))
+        .with_context(|| format!("reading '{}' from EPUB", name))?;
+
+    Ok(contents)
+}
+
+/// Resolves `href`, relative to the document at `base`, into a path within
+/// the archive
+fn resolve(base: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let mut parts: Vec<&str> = base.split('/').collect();
+    parts.pop();
+
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+
+    percent_decode(&parts.join("/"))
+}
+
+fn percent_decode(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Finds the path of the package document in `META-INF/container.xml`
+fn package_path(container: &str) -> Result<String> {
+    let mut reader = Reader::from_str(container);
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event(&mut buf) {
+            Ok(Event::Start(element)) | Ok(Event::Empty(element))
+                if element.local_name() == b"rootfile" =>
+            {
+                if let Some(path) = attribute(&element, b"full-path")? {
+                    return Ok(path);
+                }
+            }
+            Ok(Event::Eof) => bail!("EPUB container lists no package document"),
+            Err(e) => bail!("parsing EPUB container: {}", e),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+/// Package document: the title of the book and its spine, as paths within
+/// the archive
+struct Package {
+    title: Option<String>,
+    spine: Vec<String>,
+}
+
+fn parse_package(path: &str, contents: &str) -> Result<Package> {
+    let mut reader = Reader::from_str(contents);
+    let mut buf = Vec::new();
+    let mut title = None;
+    let mut in_title = false;
+    let mut items = HashMap::new();
+    let mut spine = Vec::new();
+
+    loop {
+        let event = reader.read_event(&mut buf).map_err(|e| {
+            anyhow!(
+                "parsing EPUB package document at position {}: {}",
+                reader.buffer_position(),
+                e
+            )
+        })?;
+
+        match event {
+            Event::Start(element) | Event::Empty(element) => match element.local_name() {
+                b"title" if title.is_none() => in_title = true,
+                b"item" => {
+                    if let (Some(id), Some(href)) =
+                        (attribute(&element, b"id")?, attribute(&element, b"href")?)
+                    {
+                        items.insert(id, resolve(path, &href));
+                    }
+                }
+                b"itemref" => {
+                    // non-linear items, such as footnotes, aren't part of
+                    // the reading order
+                    let linear = attribute(&element, b"linear")?.as_deref() != Some("no");
+                    if let Some(idref) = attribute(&element, b"idref")?.filter(|_| linear) {
+                        spine.push(idref);
+                    }
+                }
+                _ => {}
+            },
+            Event::Text(text) if in_title => {
+                let text = text
+                    .unescape_and_decode(&reader)
+                    .map_err(|e| anyhow!("decoding EPUB title: {}", e))?;
+                title = Some(text.trim().to_string()).filter(|title| !title.is_empty());
+            }
+            Event::End(_) => in_title = false,
+            Event::Eof => break,
+            _ => {}
+        }
+
+        buf.clear();
+    }
+
+    let spine = spine
+        .into_iter()
+        .map(|idref| {
+            items
+                .get(&idref)
+                .cloned()
+                .ok_or_else(|| anyhow!("EPUB spine refers to unknown item '{}'", idref))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Package { title, spine })
+}
+
+/// Reads the chapters of the EPUB book at `path`, in reading order
+pub fn read(path: &Path) -> Result<Book> {
+    let file = File::open(path)
+        .map(BufReader::new)
+        .with_context(|| format!("opening '{}'", path.display()))?;
+    let mut archive = ZipArchive::new(file)
+        .with_context(|| format!("reading '{}' as an EPUB archive", path.display()))?;
+
+    let package_path = package_path(&read_entry(&mut archive, CONTAINER_PATH)?)?;
+    let package = parse_package(&package_path, &read_entry(&mut archive, &package_path)?)?;
+
+    let mut chapters = Vec::new();
+    for document in &package.spine {
+        let contents = read_entry(&mut archive, document)?;
+        if !html::has_text(&contents) {
+            log::info!("Skipping EPUB document '{}' with no text", document);
+            continue;
+        }
+
+        let title =
+            html::title(&contents).unwrap_or_else(|| format!("Chapter {}", chapters.len() + 1));
+        chapters.push(Chapter { title, contents });
+    }
+
+    if chapters.is_empty() {
+        bail!("EPUB '{}' has no chapters with any text", path.display());
+    }
+
+    Ok(Book {
+        title: package.title,
+        chapters,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::write::{FileOptions, ZipWriter};
+
+    const PACKAGE: &str = r#"<?xml version="1.0"?>
+<package xmlns="http://www.idpf.org/2007/opf" xmlns:dc="http://purl.org/dc/elements/1.1/">
+  <metadata><dc:title>A Book</dc:title></metadata>
+  <manifest>
+    <item id="cover" href="Text/cover.xhtml" media-type="application/xhtml+xml"/>
+    <item id="one" href="Text/chapter%201.xhtml" media-type="application/xhtml+xml"/>
+    <item id="notes" href="Text/notes.xhtml" media-type="application/xhtml+xml"/>
+    <item id="two" href="Text/two.xhtml" media-type="application/xhtml+xml"/>
+  </manifest>
+  <spine>
+    <itemref idref="cover"/>
+    <itemref idref="one"/>
+    <itemref idref="notes" linear="no"/>
+    <itemref idref="two"/>
+  </spine>
+</package>"#;
+
+    fn write_epub(path: &Path, files: &[(&str, &str)]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        for (name, contents) in files {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(contents.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn reads_chapters_in_reading_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("book.epub");
+        write_epub(
+            &path,
+            &[
+                (
+                    CONTAINER_PATH,
+                    r#"<container><rootfiles><rootfile full-path="OEBPS/content.opf"/></rootfiles></container>"#,
+                ),
+                ("OEBPS/content.opf", PACKAGE),
+                (
+                    "OEBPS/Text/cover.xhtml",
+                    r#"<body><img src="cover.jpg"/></body>"#,
+                ),
+                (
+                    "OEBPS/Text/chapter 1.xhtml",
+                    "<h1>Beginning</h1><p>Text.</p>",
+                ),
+                ("OEBPS/Text/notes.xhtml", "<p>Notes.</p>"),
+                ("OEBPS/Text/two.xhtml", "<p>More text.</p>"),
+            ],
+        );
+
+        let book = read(&path).unwrap();
+        assert_eq!(book.title.as_deref(), Some("A Book"));
+        let titles: Vec<&str> = book
+            .chapters
+            .iter()
+            .map(|chapter| chapter.title.as_str())
+            .collect();
+        assert_eq!(titles, ["Beginning", "Chapter 2"]);
+        assert!(book.chapters[1].contents.contains("More text."));
+    }
+
+    #[test]
+    fn resolves_paths_within_the_archive() {
+        assert_eq!(
+            resolve("OEBPS/content.opf", "Text/a.xhtml"),
+            "OEBPS/Text/a.xhtml"
+        );
+        assert_eq!(
+            resolve("OEBPS/Text/a.xhtml", "../b.xhtml#note"),
+            "OEBPS/b.xhtml"
+        );
+        assert_eq!(resolve("content.opf", "./c%20d.xhtml"), "c d.xhtml");
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+
+    #[test]
+    fn rejects_books_without_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("empty.epub");
+        write_epub(
+            &path,
+            &[
+                (
+                    CONTAINER_PATH,
+                    r#"<container><rootfiles><rootfile full-path="content.opf"/></rootfiles></container>"#,
+                ),
+                (
+                    "content.opf",
+                    r#"<package><manifest><item id="c" href="c.xhtml"/></manifest><spine><itemref idref="c"/></spine></package>"#,
+                ),
+                ("c.xhtml", "<body></body>"),
+            ],
+        );
+
+        assert!(read(&path).is_err());
+    }
+}
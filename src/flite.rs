@@ -0,0 +1,206 @@
+//! The embedded flite build and the way it is invoked.
+
+use serde_json::json;
+use std::time::Duration;
+
+pub const FLITE_JS: &[u8] = include_bytes!("../assets/flite.js");
+pub const FLITE_WASM: &[u8] = include_bytes!("../assets/flite.wasm");
+/// Snippet prepended to [`FLITE_JS`] for gWasm tasks; it unpacks the header
+/// written by [`encode_input`]
+pub const PRELUDE_JS: &[u8] = include_bytes!("../assets/prelude.js");
+
+/// Voices compiled into the bundled flite build, along with a short description
+///
+/// The names are those listed by `flite -lv` for `assets/flite.wasm`, e.g.
+/// `node assets/flite.js -lv`, and need updating along with the build; the
+/// descriptions are our own. The first voice is flite's default.
+pub const VOICES: &[(&str, &str)] = &[
+    ("kal", "US English male, diphone, 8kHz (default)"),
+    ("kal16", "US English male, diphone, 16kHz"),
+    ("awb", "Scottish English male, 16kHz"),
+    ("rms", "US English male, 16kHz"),
+    ("slt", "US English female, 16kHz"),
+    ("awb_time", "Scottish English male, talking clock only"),
+];
+
+/// Mean pitch in Hz of the bundled diphone voices, as their voice
+/// definitions set it
+///
+/// flite's `f0_shift` is meant for its clustergen voices, so the pitch of
+/// these is moved by setting their target mean instead.
+const DIPHONE_F0_MEANS: &[(&str, f64)] = &[("kal", 95.0), ("kal16", 105.0)];
+
+pub fn is_voice(name: &str) -> bool {
+    VOICES.iter().any(|(voice, _)| *voice == name)
+}
+
+/// Typical speaking rate of the bundled voices at normal rate, in words per
+/// minute
+pub const WORDS_PER_MINUTE: f64 = 160.0;
+
+/// flite command-line options of a subtask
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub voice: Option<String>,
+    /// Speaking rate relative to the voice's default
+    pub rate: f64,
+    /// Pitch relative to the voice's default
+    pub pitch: f64,
+    /// Volume relative to the voice's default
+    pub volume: f64,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            voice: None,
+            rate: 1.0,
+            pitch: 1.0,
+            volume: 1.0,
+        }
+    }
+}
+
+impl Options {
+    /// Works out flite args corresponding to the options, which go before
+    /// the input and output file names
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if let Some(voice) = &self.voice {
+            args.push("-voice".into());
+            args.push(voice.clone());
+        }
+
+        let mut setf = |feature: &str, value: f64| {
+            args.push("--setf".into());
+            args.push(format!("{}={}", feature, value));
+        };
+        if is_changed(self.rate) {
+            setf("duration_stretch", 1.0 / self.rate);
+        }
+        if is_changed(self.pitch) {
+            let voice = self.voice.as_deref().unwrap_or(VOICES[0].0);
+            match DIPHONE_F0_MEANS.iter().find(|(name, _)| *name == voice) {
+                Some((_, mean)) => setf("int_f0_target_mean", mean * self.pitch),
+                None => setf("f0_shift", self.pitch),
+            }
+        }
+
+        args
+    }
+
+    /// Volume the synthesized audio is scaled to, in percent of the voice's
+    /// default, unless left at it
+    ///
+    /// None of the bundled voices heed flite's `gain`, so the volume is
+    /// applied to their output instead.
+    pub fn volume_percent(&self) -> Option<u16> {
+        let percent = (self.volume * 100.0).round() as u16;
+        if percent == 100 {
+            None
+        } else {
+            Some(percent)
+        }
+    }
+
+    /// Estimates how long it takes to speak `num_words` words with these
+    /// options, going by [`WORDS_PER_MINUTE`]
+    pub fn estimate_duration(&self, num_words: usize) -> Duration {
+        Duration::from_secs_f64(num_words as f64 * 60.0 / (WORDS_PER_MINUTE * self.rate))
+    }
+}
+
+fn is_changed(value: f64) -> bool {
+    (value - 1.0).abs() > f64::EPSILON
+}
+
+/// flite option loading lexicon addenda from the file following it
+pub const ADD_LEX_ARG: &str = "-add_lex";
+
+/// Encodes subtask input for a gWasm task
+///
+/// gWasm invokes flite with fixed args and just the input file, so any
+/// extra `args` are passed in a header line prepended to the input text
+/// instead, along with the `lexicon` addenda, which the prelude writes to a
+/// file of its own for flite to load.
+pub fn encode_input(args: &[String], lexicon: Option<&str>, text: &str) -> Vec<u8> {
+    let header = match lexicon {
+        Some(lexicon) => json!({ "args": args, "lexicon": lexicon }),
+        None if args.is_empty() => return text.as_bytes().to_vec(),
+        None => json!({ "args": args }),
+    };
+
+    format!("#g_flite {}\n{}", header, text).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn voices_are_in_the_bundled_build() {
+        for (voice, _) in VOICES {
+            // flite registers each voice under its name, a C string
+            let name = [b"\0", voice.as_bytes(), b"\0"].concat();
+            assert!(
+                FLITE_WASM
+                    .windows(name.len())
+                    .any(|bytes| bytes == &name[..]),
+                "voice '{}' is missing from assets/flite.wasm",
+                voice
+            );
+        }
+    }
+
+    fn options(voice: Option<&str>) -> Options {
+        Options {
+            voice: voice.map(String::from),
+            rate: 0.8,
+            pitch: 1.2,
+            volume: 2.0,
+        }
+    }
+
+    #[test]
+    fn args_per_voice_type() {
+        // diphone voices, including the default one, get their mean pitch
+        // moved
+        assert_eq!(
+            options(None).args(),
+            [
+                "--setf",
+                "duration_stretch=1.25",
+                "--setf",
+                "int_f0_target_mean=114"
+            ]
+        );
+        assert_eq!(
+            options(Some("kal16")).args(),
+            [
+                "-voice",
+                "kal16",
+                "--setf",
+                "duration_stretch=1.25",
+                "--setf",
+                "int_f0_target_mean=126"
+            ]
+        );
+        // clustergen voices get it shifted
+        assert_eq!(
+            options(Some("slt")).args(),
+            [
+                "-voice",
+                "slt",
+                "--setf",
+                "duration_stretch=1.25",
+                "--setf",
+                "f0_shift=1.2"
+            ]
+        );
+        // volume is never passed to flite
+        assert_eq!(options(Some("rms")).volume_percent(), Some(200));
+        assert!(Options::default().args().is_empty());
+        assert_eq!(Options::default().volume_percent(), None);
+    }
+}
@@ -0,0 +1,190 @@
+//! Reading of HTML input.
+//!
+//! The document is parsed the way a browser would, so malformed markup is
+//! fine, and only the text of its body is kept; scripts, styles, images,
+//! navigation menus and the like are dropped. Block elements separate
+//! paragraphs, headings and list items end a sentence so that flite pauses
+//! after them, each heading makes up a segment of its own, which the
+//! paragraph pause follows, and code blocks (`<pre>`) are skipped or
+//! replaced with a phrase.
+
+use super::input::Prose;
+use super::split::Segment;
+use scraper::{ElementRef, Html, Selector};
+
+/// Elements whose contents are not meant to be read
+const SKIPPED: &[&str] = &[
+    "head", "script", "style", "noscript", "template", "nav", "svg", "math", "canvas", "iframe",
+    "object", "embed", "img", "audio", "video", "select",
+];
+
+/// Elements which stand in a paragraph of their own
+const BLOCKS: &[&str] = &[
+    "p",
+    "div",
+    "section",
+    "article",
+    "main",
+    "header",
+    "footer",
+    "aside",
+    "blockquote",
+    "figure",
+    "figcaption",
+    "table",
+    "form",
+    "fieldset",
+    "ul",
+    "ol",
+    "dl",
+    "address",
+    "details",
+    "summary",
+    "hr",
+];
+
+/// Elements which end a sentence
+const SENTENCES: &[&str] = &["li", "dt", "dd", "tr", "caption", "label", "legend"];
+
+const HEADINGS: &[&str] = &["h1", "h2", "h3", "h4", "h5", "h6"];
+
+fn read_element(element: ElementRef, code_phrase: Option<&str>, prose: &mut Prose) {
+    let name = element.value().name();
+
+    if SKIPPED.contains(&name) {
+        return;
+    }
+
+    if name == "pre" {
+        match code_phrase {
+            Some(phrase) => prose.push_paragraph(phrase),
+            None => prose.end_paragraph(),
+        }
+        return;
+    }
+
+    if name == "br" {
+        prose.space();
+        return;
+    }
+
+    let is_heading = HEADINGS.contains(&name);
+    if is_heading {
+        prose.start_heading();
+    } else if BLOCKS.contains(&name) {
+        prose.end_paragraph();
+    }
+
+    for child in element.children() {
+        if let Some(text) = child.value().as_text() {
+            prose.push(text);
+        } else if let Some(child) = ElementRef::wrap(child) {
+            read_element(child, code_phrase, prose);
+        }
+    }
+
+    if is_heading {
+        prose.end_heading();
+        return;
+    }
+
+    if SENTENCES.contains(&name) {
+        prose.end_sentence();
+    } else if name == "td" || name == "th" {
+        prose.space();
+    }
+    if BLOCKS.contains(&name) {
+        prose.end_paragraph();
+    }
+}
+
+/// Parses an HTML document into segments; see [`super::input::read`]
+pub fn parse(contents: &str, code_phrase: Option<&str>) -> Vec<Segment> {
+    let document = Html::parse_document(contents);
+    let mut prose = Prose::default();
+    read_element(document.root_element(), code_phrase, &mut prose);
+
+    prose.into_segments()
+}
+
+/// Checks whether an HTML document has any text to read
+pub fn has_text(contents: &str) -> bool {
+    parse(contents, None)
+        .iter()
+        .any(|segment| segment.text.split_whitespace().next().is_some())
+}
+
+/// Works out the title of an HTML document from its highest-level heading,
+/// or failing that, its `<title>`
+pub fn title(contents: &str) -> Option<String> {
+    let document = Html::parse_document(contents);
+
+    HEADINGS
+        .iter()
+        .chain(&["title"])
+        .filter_map(|name| Selector::parse(name).ok())
+        .filter_map(|selector| document.select(&selector).next())
+        .map(|element| element.text().collect::<Vec<_>>().join(" "))
+        .map(|text| text.split_whitespace().collect::<Vec<_>>().join(" "))
+        .find(|text| !text.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(contents: &str, code_phrase: Option<&str>) -> String {
+        parse(contents, code_phrase)
+            .into_iter()
+            .map(|segment| segment.text)
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    #[test]
+    fn keeps_the_text_of_the_body() {
+        let contents = r#"<html><head><title>Page</title><style>p { color: red }</style></head>
+            <body><nav>Home | About</nav><h1>Title</h1>
+            <p>Some <b>bold</b><br>text.</p><script>alert(1)</script>
+            <ul><li>one</li><li>two</li></ul></body></html>"#;
+        assert_eq!(
+            text(contents, None),
+            "Title.\n\nSome bold text.\n\none. two."
+        );
+    }
+
+    #[test]
+    fn headings_make_up_segments_of_their_own() {
+        let segments = parse(
+            "<h1>One</h1><p>Text</p><section><h2>Two</h2><p>More text</p></section>",
+            None,
+        );
+        let texts: Vec<&str> = segments.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, ["One.\n\n", "Text\n\n", "Two.\n\n", "More text\n\n"]);
+    }
+
+    #[test]
+    fn code_blocks() {
+        let contents = "<p>Before</p><pre>let x = 1;</pre><p>After</p>";
+        assert_eq!(text(contents, None), "Before\n\nAfter");
+        assert_eq!(
+            text(contents, Some("Code sample.")),
+            "Before\n\nCode sample.\n\nAfter"
+        );
+    }
+
+    #[test]
+    fn titles_and_text() {
+        assert_eq!(
+            title("<title>Book</title><h2>Chapter\n  One</h2><h3>Part</h3>").as_deref(),
+            Some("Chapter One")
+        );
+        assert_eq!(
+            title("<title>Book</title><p>Text</p>").as_deref(),
+            Some("Book")
+        );
+        assert!(has_text("<p>Text</p>"));
+        assert!(!has_text(r#"<body><img src="cover.jpg"></body>"#));
+    }
+}
@@ -0,0 +1,170 @@
+//! Readers turning input documents into segments of text to synthesize.
+
+use super::flite;
+use super::html;
+use super::markdown;
+use super::split::{Segment, CLOSING};
+use super::ssml;
+use anyhow::{bail, Result};
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    Text,
+    Ssml,
+    Markdown,
+    Html,
+}
+
+impl InputFormat {
+    /// Infers the input format from the file extension, defaulting to plain text
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ssml") => InputFormat::Ssml,
+            Some("md") | Some("markdown") => InputFormat::Markdown,
+            Some("html") | Some("htm") | Some("xhtml") => InputFormat::Html,
+            _ => InputFormat::Text,
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(InputFormat::Text),
+            "ssml" => Ok(InputFormat::Ssml),
+            "markdown" => Ok(InputFormat::Markdown),
+            "html" => Ok(InputFormat::Html),
+            other => bail!(
+                "unknown input format '{}'; expected one of: text, ssml, markdown, html",
+                other
+            ),
+        }
+    }
+}
+
+/// Plain text extracted from a marked-up document, laid out the way the
+/// splitter expects it: paragraphs separated by a blank line, and headings
+/// and list items ending a sentence so that flite pauses after them
+///
+/// Each heading makes up a segment of its own, which the paragraph pause
+/// follows just as it does any paragraph; see [`crate::split::split_paragraphs`].
+#[derive(Debug, Default)]
+pub struct Prose {
+    segments: Vec<Segment>,
+    text: String,
+}
+
+impl Prose {
+    /// Appends running text, collapsing any whitespace in it as HTML does
+    pub fn push(&mut self, text: &str) {
+        let mut words = text.split_whitespace();
+
+        if text.starts_with(char::is_whitespace) {
+            self.space();
+        }
+        if let Some(word) = words.next() {
+            self.text.push_str(word);
+        }
+        for word in words {
+            self.text.push(' ');
+            self.text.push_str(word);
+        }
+        if text.ends_with(char::is_whitespace) {
+            self.space();
+        }
+    }
+
+    /// Separates whatever comes next from the preceding word
+    pub fn space(&mut self) {
+        if !self.text.is_empty() && !self.text.ends_with(char::is_whitespace) {
+            self.text.push(' ');
+        }
+    }
+
+    /// Ends the current sentence, adding a full stop if it lacks any
+    /// punctuation
+    pub fn end_sentence(&mut self) {
+        let end = self.text.trim_end().len();
+        let last = self.text[..end].trim_end_matches(CLOSING).chars().last();
+
+        if last.is_some_and(char::is_alphanumeric) {
+            self.text.insert(end, '.');
+        }
+        self.space();
+    }
+
+    /// Ends the current paragraph
+    pub fn end_paragraph(&mut self) {
+        let end = self.text.trim_end().len();
+        self.text.truncate(end);
+
+        if !self.text.is_empty() {
+            self.text.push_str("\n\n");
+        }
+    }
+
+    /// Appends a paragraph of its own, e.g. one standing in for a code block
+    pub fn push_paragraph(&mut self, text: &str) {
+        self.end_paragraph();
+        self.push(text);
+        self.end_sentence();
+        self.end_paragraph();
+    }
+
+    /// Starts a heading, ending the segment before it
+    pub fn start_heading(&mut self) {
+        self.end_paragraph();
+        self.end_segment();
+    }
+
+    /// Ends a heading, making it a segment of its own
+    pub fn end_heading(&mut self) {
+        self.end_sentence();
+        self.end_paragraph();
+        self.end_segment();
+    }
+
+    fn end_segment(&mut self) {
+        if self.text.is_empty() {
+            return;
+        }
+
+        self.segments.push(Segment {
+            text: std::mem::take(&mut self.text),
+            flite: None,
+            pause: None,
+        });
+    }
+
+    pub fn into_segments(mut self) -> Vec<Segment> {
+        self.end_segment();
+        self.segments
+    }
+}
+
+/// Reads `contents` in the given format, using `base` as the flite options
+/// where the document doesn't say otherwise
+///
+/// Code blocks in Markdown and HTML documents are skipped, or if
+/// `code_phrase` is given, replaced with it.
+pub fn read(
+    format: InputFormat,
+    contents: &str,
+    base: &flite::Options,
+    code_phrase: Option<&str>,
+) -> Result<Vec<Segment>> {
+    match format {
+        InputFormat::Text => Ok(vec![Segment {
+            text: contents.to_string(),
+            flite: None,
+            pause: None,
+        }]),
+        InputFormat::Ssml => ssml::parse(contents, base),
+        InputFormat::Markdown => Ok(markdown::parse(contents, code_phrase)),
+        InputFormat::Html => Ok(html::parse(contents, code_phrase)),
+    }
+}
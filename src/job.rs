@@ -0,0 +1,234 @@
+//! Synthesis of one or more documents as a single task, whose state is kept
+//! in a workspace, so that it can be resumed where it left off; see
+//! [`Manifest`].
+
+use super::cache::Cache;
+use super::document::{self, Document};
+use super::input::InputFormat;
+use super::manifest::{self, Manifest};
+use super::output::{self, OutputFormat};
+use super::synth::{self, Combined};
+use super::Synthesizer;
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Extension of the CUE sheet holding the chapter markers of a book
+const CUE_EXTENSION: &str = "cue";
+
+#[derive(Debug, Clone)]
+pub struct Job {
+    workspace: PathBuf,
+    manifest: Manifest,
+}
+
+impl Job {
+    /// Splits `documents` into chunks and records them in `workspace`;
+    /// `input_format` overrides the format inferred from the extension of
+    /// each input file
+    pub fn create(
+        workspace: &Path,
+        documents: &[Document],
+        synth: &Synthesizer,
+        input_format: Option<InputFormat>,
+        output_format: OutputFormat,
+    ) -> Result<Self> {
+        let mut manifest = Manifest::new(output_format, *synth.seams());
+        for document in documents {
+            let (chunks, chapters) = document.split(synth, input_format)?;
+            manifest.add_document(
+                document.input.clone(),
+                document.output.clone(),
+                synth.task(&chunks),
+                &synth.pauses(&chunks),
+                document.title().map(String::from),
+                chapters,
+            );
+        }
+        manifest.save(workspace)?;
+
+        Ok(Self {
+            workspace: workspace.to_path_buf(),
+            manifest,
+        })
+    }
+
+    /// Picks up the task recorded in `workspace` by an earlier run
+    pub fn resume(workspace: &Path) -> Result<Self> {
+        Ok(Self {
+            workspace: workspace.to_path_buf(),
+            manifest: Manifest::load(workspace)?,
+        })
+    }
+
+    pub fn workspace(&self) -> &Path {
+        &self.workspace
+    }
+
+    pub fn manifest(&self) -> &Manifest {
+        &self.manifest
+    }
+
+    pub fn num_chunks(&self) -> usize {
+        self.manifest.entries.len()
+    }
+
+    pub fn num_pending(&self) -> usize {
+        self.manifest.pending().len()
+    }
+
+    /// Takes the chunks which are not done yet from `cache` where possible,
+    /// returning how many of them were found there
+    pub fn restore_cached(&mut self, cache: &Cache) -> Result<usize> {
+        let mut num_cached = 0;
+        for i in self.manifest.pending() {
+            match cache.get(&self.manifest.entries[i].subtask) {
+                Ok(Some(output)) => {
+                    self.manifest.save_result(&self.workspace, i, &output)?;
+                    num_cached += 1;
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("Reading chunk {} from cache failed: {:#}", i, e),
+            }
+        }
+
+        Ok(num_cached)
+    }
+
+    /// Computes the chunks which are not done yet, storing each result in
+    /// the workspace as soon as it is available; see
+    /// [`Synthesizer::compute`] for `on_retry`
+    pub fn compute<R>(&mut self, synth: &Synthesizer, on_retry: R) -> Result<()>
+    where
+        R: FnMut(usize, u32),
+    {
+        let pending = self.manifest.pending();
+        let task = self.manifest.task(&pending);
+        log::debug!("g_flite run task = {:?}", task);
+
+        let workspace = &self.workspace;
+        let manifest = &mut self.manifest;
+        synth.compute(task, on_retry, |j, output| {
+            manifest.save_result(workspace, pending[j], &output)
+        })
+    }
+
+    /// Stores the output of chunk `i`, computed outside of [`Job::compute`],
+    /// and marks the chunk as done
+    pub fn save_result(&mut self, i: usize, output: &[u8]) -> Result<()> {
+        self.manifest.save_result(&self.workspace, i, output)
+    }
+
+    /// Combines the results of the chunks of `document` into its output,
+    /// or stdout, along with a CUE sheet marking its chapters if it has
+    /// any; nothing is written if the document has no chunks
+    pub fn combine(&self, document: &manifest::Document) -> Result<Option<Combined>> {
+        let results = document
+            .entries
+            .clone()
+            .map(|i| {
+                let path = Manifest::result_path(&self.workspace, i);
+                File::open(&path)
+                    .map(BufReader::new)
+                    .with_context(|| format!("opening result of chunk '{}'", i))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let output_format = self.manifest.output_format;
+        let output = &document.output;
+        let combined = synth::combine(
+            results,
+            &self.manifest.pauses(document),
+            &self.manifest.seams,
+            |sample_rate| {
+                if document::is_stdio(output) {
+                    let stdout = BufWriter::new(io::stdout());
+                    output::stream(output_format, stdout, sample_rate)
+                } else {
+                    output::create(output_format, output, sample_rate)
+                }
+            },
+        )?;
+
+        if let Some(combined) = &combined {
+            if !document.chapters.is_empty() {
+                self.mark_chapters(document, combined)?;
+            }
+        }
+
+        Ok(combined)
+    }
+
+    /// Writes a CUE sheet next to the output of a book, marking where each
+    /// of its chapters starts
+    fn mark_chapters(&self, document: &manifest::Document, combined: &Combined) -> Result<()> {
+        let tracks: Vec<_> = document
+            .chapters
+            .iter()
+            .map(|chapter| {
+                let offset = combined.offsets.get(chapter.start).cloned().unwrap_or(0);
+                let start =
+                    Duration::from_secs_f64(offset as f64 / f64::from(combined.sample_rate));
+                (chapter.title.clone(), start)
+            })
+            .collect();
+
+        output::write_cue_sheet(
+            &cue_path(document),
+            &document.output,
+            self.manifest.output_format,
+            document.title.as_deref(),
+            &tracks,
+        )
+    }
+}
+
+/// Path of the CUE sheet marking the chapters of `document`
+pub fn cue_path(document: &manifest::Document) -> PathBuf {
+    document.output.with_extension(CUE_EXTENSION)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Mock;
+    use crate::document::Contents;
+    use std::fs;
+
+    #[test]
+    fn resumes_and_combines() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.txt");
+        fs::write(&input, "One two three. Four five six. Seven eight nine.").unwrap();
+        let output = dir.path().join("out.wav");
+        let documents = [Document {
+            input,
+            output: output.clone(),
+            contents: Contents::File,
+        }];
+        let workspace = dir.path().join("workspace");
+        fs::create_dir(&workspace).unwrap();
+
+        let synth = Synthesizer::builder().subtasks(3).build().unwrap();
+        let job = Job::create(&workspace, &documents, &synth, None, OutputFormat::Wav).unwrap();
+        assert_eq!(job.num_pending(), 3);
+
+        // computing needs a backend, which is only set once there is
+        // something to compute
+        let mut job = Job::resume(&workspace).unwrap();
+        assert!(job.compute(&synth, |_, _| {}).is_err());
+        let synth = Synthesizer::builder()
+            .backend(Box::new(Mock))
+            .subtasks(3)
+            .build()
+            .unwrap();
+        job.compute(&synth, |_, _| {}).unwrap();
+        assert_eq!(Job::resume(&workspace).unwrap().num_pending(), 0);
+
+        let combined = job.combine(&job.manifest().documents[0]).unwrap();
+        assert_eq!(combined.unwrap().offsets.len(), 3);
+        assert!(output.is_file());
+    }
+}
@@ -0,0 +1,221 @@
+//! Custom pronunciations of words flite gets wrong, such as product names
+//! and jargon.
+//!
+//! A lexicon file holds one entry per line. An entry either respells a word
+//! with ones flite reads out right, e.g. `Golem = goal em`, or gives its
+//! phonemes in the syntax of flite's lexicon addenda, e.g.
+//! `gwasm : g w aa1 z ah0 m`. Respellings are applied to the text of each
+//! chunk before it is sent off to be synthesized, while phonemes are passed
+//! on to flite with its `-add_lex` option, for the chunks using the word.
+
+use anyhow::{bail, Context, Result};
+use regex::{Captures, Regex};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+/// Separates a word from its respelling in a lexicon file
+const RESPELLING_SEPARATOR: char = '=';
+/// Separates a word, and optionally its part of speech, from its phonemes
+const PHONEMES_SEPARATOR: &str = ":";
+
+/// Respellings and phonemes of words, loaded from lexicon files
+#[derive(Debug, Clone, Default)]
+pub struct Lexicon {
+    /// Respellings, keyed by the lowercase word
+    respellings: HashMap<String, String>,
+    /// Matches any of the respelled words, ignoring case
+    pattern: Option<Regex>,
+    /// Entries of flite's lexicon addenda, along with the lowercase word
+    /// each is for
+    addenda: Vec<(String, String)>,
+}
+
+/// Parses the word of an entry in the syntax of flite's lexicon addenda,
+/// `word [pos] : phonemes`
+fn parse_addenda_word(entry: &str) -> Result<String> {
+    let tokens: Vec<&str> = entry.split_whitespace().collect();
+    match tokens.iter().position(|&token| token == PHONEMES_SEPARATOR) {
+        // the word may be followed by its part of speech
+        Some(i @ 1..=2) if i + 1 < tokens.len() => Ok(tokens[0].trim_matches('"').to_lowercase()),
+        _ => bail!(
+            "expected 'word {} phonemes', all separated by spaces",
+            PHONEMES_SEPARATOR
+        ),
+    }
+}
+
+impl Lexicon {
+    /// Adds the entries in the file at `path`, one `word = respelling` or
+    /// `word : phonemes` per line; empty lines and lines starting with `#`
+    /// are skipped
+    ///
+    /// Entries for a word already in the lexicon replace the earlier ones.
+    pub fn load(&mut self, path: &Path) -> Result<()> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("reading lexicon file '{}'", path.display()))?;
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            self.add_entry(line).with_context(|| {
+                format!(
+                    "parsing line {} of lexicon file '{}'",
+                    i + 1,
+                    path.display()
+                )
+            })?;
+        }
+
+        self.update_pattern()
+    }
+
+    fn add_entry(&mut self, entry: &str) -> Result<()> {
+        if let Some((word, respelling)) = entry.split_once(RESPELLING_SEPARATOR) {
+            let (word, respelling) = (word.trim(), respelling.trim());
+            if word.is_empty() || respelling.is_empty() {
+                bail!("expected 'word {} respelling'", RESPELLING_SEPARATOR);
+            }
+            self.respellings
+                .insert(word.to_lowercase(), respelling.to_string());
+        } else {
+            let word = parse_addenda_word(entry)?;
+            // flite looks words up in lowercase
+            let entry = entry.to_lowercase();
+            self.addenda.retain(|(other, _)| *other != word);
+            self.addenda.push((word, entry));
+        }
+
+        Ok(())
+    }
+
+    fn update_pattern(&mut self) -> Result<()> {
+        if self.respellings.is_empty() {
+            self.pattern = None;
+            return Ok(());
+        }
+
+        // longer words go first, so that e.g. "gWasm API" wins over "gWasm"
+        let mut words: Vec<&String> = self.respellings.keys().collect();
+        words.sort_by(|a, b| b.len().cmp(&a.len()).then(a.cmp(b)));
+        let is_word_char = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric() || c == '_');
+        let alternatives: Vec<String> = words
+            .iter()
+            .map(|word| {
+                // words starting or ending with a symbol, such as "C++",
+                // can't be delimited by a word boundary on that side
+                let start = if is_word_char(word.chars().next()) {
+                    r"\b"
+                } else {
+                    ""
+                };
+                let end = if is_word_char(word.chars().last()) {
+                    r"\b"
+                } else {
+                    ""
+                };
+                format!("{}{}{}", start, regex::escape(word), end)
+            })
+            .collect();
+
+        self.pattern = Some(
+            Regex::new(&format!("(?i)(?:{})", alternatives.join("|")))
+                .context("building pattern of respelled words")?,
+        );
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.respellings.is_empty() && self.addenda.is_empty()
+    }
+
+    /// Replaces the words of `text` which have a respelling with it
+    pub fn respell(&self, text: &str) -> String {
+        match &self.pattern {
+            Some(pattern) => pattern
+                .replace_all(text, |caps: &Captures| {
+                    self.respellings
+                        .get(&caps[0].to_lowercase())
+                        .map_or_else(|| caps[0].to_string(), String::clone)
+                })
+                .into_owned(),
+            None => text.to_string(),
+        }
+    }
+
+    /// Works out the lexicon addenda for flite to synthesize `text` with,
+    /// made of the entries for the words it contains, if there are any
+    pub fn addenda(&self, text: &str) -> Option<String> {
+        if self.addenda.is_empty() {
+            return None;
+        }
+
+        let words: HashSet<String> = text
+            .split(|c: char| !c.is_alphanumeric() && c != '\'')
+            .map(|word| word.trim_matches('\'').to_lowercase())
+            .collect();
+        let addenda: String = self
+            .addenda
+            .iter()
+            .filter(|(word, _)| words.contains(word))
+            .map(|(_, entry)| format!("{}\n", entry))
+            .collect();
+
+        Some(addenda).filter(|addenda| !addenda.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lexicon(entries: &[&str]) -> Lexicon {
+        let mut lexicon = Lexicon::default();
+        for entry in entries {
+            lexicon.add_entry(entry).unwrap();
+        }
+        lexicon.update_pattern().unwrap();
+        lexicon
+    }
+
+    #[test]
+    fn respells_whole_words_ignoring_case() {
+        let lexicon = lexicon(&["Golem = goal em", "C++ = see plus plus"]);
+        assert_eq!(
+            lexicon.respell("GOLEM and golems, in C++."),
+            "goal em and golems, in see plus plus."
+        );
+    }
+
+    #[test]
+    fn prefers_longer_words() {
+        let lexicon = lexicon(&["gWasm = gee wasm", "gWasm API = gee wasm A P I"]);
+        assert_eq!(lexicon.respell("The gWasm API"), "The gee wasm A P I");
+    }
+
+    #[test]
+    fn addenda_of_the_words_used() {
+        let lexicon = lexicon(&[
+            "gwasm : g w aa1 z ah0 m",
+            "golem n : g ow1 l ah0 m",
+            "gwasm : g w aa1 s ah0 m",
+        ]);
+        assert_eq!(
+            lexicon.addenda("Running GWASM today").as_deref(),
+            Some("gwasm : g w aa1 s ah0 m\n")
+        );
+        assert_eq!(lexicon.addenda("Nothing here"), None);
+    }
+
+    #[test]
+    fn rejects_malformed_entries() {
+        let mut lexicon = Lexicon::default();
+        assert!(lexicon.add_entry("word =").is_err());
+        assert!(lexicon.add_entry("word : ").is_err());
+        assert!(lexicon.add_entry("just some words").is_err());
+    }
+}
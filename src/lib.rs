@@ -0,0 +1,49 @@
+//! flite, a text-to-speech program, distributed over Golem network.
+//!
+//! The input text is split into chunks along its paragraphs, sentences and
+//! clauses, each chunk is synthesized as a separate subtask on a
+//! [`backend::Backend`] and the resulting audio is stitched back together.
+//! [`Synthesizer`] ties these steps together. A [`job::Job`] synthesizes
+//! whole documents, keeping its state in a workspace so that it can be
+//! resumed, and a [`plan::Plan`] shows what computing them would take; the
+//! `g_flite` binary is a thin command-line wrapper around these.
+//!
+//! ```no_run
+//! use g_flite::backend::Mock;
+//! use g_flite::output::OutputFormat;
+//! use g_flite::Synthesizer;
+//! use std::fs::File;
+//!
+//! # fn main() -> anyhow::Result<()> {
+//! let synthesizer = Synthesizer::builder()
+//!     .backend(Box::new(Mock))
+//!     .subtasks(2)
+//!     .build()?;
+//! let file = File::create("hello.flac")?;
+//! synthesizer.synthesize_to("Hello there. How are you?", OutputFormat::Flac, file)?;
+//! # Ok(())
+//! # }
+//! ```
+
+pub mod audio;
+pub mod backend;
+pub mod cache;
+pub mod cancel;
+pub mod document;
+pub mod epub;
+pub mod flite;
+mod html;
+pub mod input;
+pub mod job;
+pub mod lexicon;
+pub mod manifest;
+mod markdown;
+pub mod normalize;
+pub mod output;
+pub mod plan;
+pub mod progress;
+pub mod split;
+mod ssml;
+pub mod synth;
+
+pub use synth::{Audio, Chunking, Synthesizer, SynthesizerBuilder};
@@ -1,61 +1,325 @@
 mod app;
+mod detached;
 
+use anyhow::{anyhow, Context};
 use app::App;
 use colored::Colorize;
 use env_logger::{Builder, Env};
+use g_flite::backend::BackendKind;
+use g_flite::cancel::CancelToken;
+use g_flite::flite;
+use g_flite::input::InputFormat;
+use g_flite::output::OutputFormat;
+use g_flite::split::Balance;
 use gwasm_api::prelude::Timeout;
-use std::{convert::TryInto, path::PathBuf};
+use std::{convert::TryInto, path::PathBuf, process, time::Duration};
+use structopt::clap::{AppSettings, ArgMatches};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 #[structopt(
     name = "g_flite",
     author = "Golem RnD Team <contact@golem.network>",
-    about = "flite, a text-to-speech program, distributed over Golem network"
+    about = "flite, a text-to-speech program, distributed over Golem network",
+    raw(setting = "AppSettings::SubcommandsNegateReqs")
 )]
+struct Cli {
+    #[structopt(flatten)]
+    opt: Opt,
+
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Splits the input and sends it to Golem without waiting on it, printing the task id
+    ///
+    /// Takes the same args and options as running g_flite directly, the
+    /// options either before or after the command. The task is created on the
+    /// Golem node, which computes it on its own once this command exits; use
+    /// the status and fetch commands, with the id the node gave the task, to
+    /// follow it up.
+    #[structopt(name = "submit")]
+    Submit {
+        /// Input text file or EPUB book, or - to read it from stdin; with --out-dir, a dir or glob pattern
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Output audio file
+        #[structopt(parse(from_os_str), raw(required_unless = r#""out_dir""#))]
+        output: Option<PathBuf>,
+    },
+
+    /// Synthesizes many documents at once, in a single Golem task
+    ///
+    /// Takes the same options as running g_flite directly, either before or
+    /// after the command, but instead of the output file, requires --out-dir.
+    /// The input is a dir, all of whose files are synthesized, or a glob
+    /// pattern, which needs quoting so that the shell doesn't expand it. EPUB
+    /// books are written one file per chapter.
+    #[structopt(name = "batch")]
+    Batch {
+        /// Input dir, or glob pattern
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+
+    /// Asks the Golem node how far along a submitted task is
+    #[structopt(name = "status")]
+    Status {
+        /// Task id printed by the submit command
+        id: String,
+    },
+
+    /// Combines the output of a submitted task once the Golem node is done with it
+    #[structopt(name = "fetch")]
+    Fetch {
+        /// Task id printed by the submit command
+        id: String,
+    },
+}
+
+#[derive(Debug, StructOpt)]
 struct Opt {
-    /// Input text file
-    #[structopt(parse(from_os_str))]
-    input: PathBuf,
+    /// Input text file or EPUB book, or - to read it from stdin; with --out-dir, a dir or glob pattern
+    #[structopt(
+        parse(from_os_str),
+        raw(required_unless_one = r#"&["list_voices", "resume"]"#)
+    )]
+    input: Option<PathBuf>,
+
+    /// Output audio file, or - to stream it to stdout
+    #[structopt(
+        parse(from_os_str),
+        raw(required_unless_one = r#"&["list_voices", "resume", "out_dir", "print_normalized"]"#),
+        conflicts_with = "out_dir"
+    )]
+    output: Option<PathBuf>,
+
+    /// Synthesizes every file in the input dir, or matching the input glob
+    /// pattern, writing the output of each into this dir
+    ///
+    /// All of the documents are computed as a single Golem task. Each output
+    /// file is named after its input file, with the extension of the output
+    /// format.
+    #[structopt(long = "out-dir", parse(from_os_str), raw(global = "true"))]
+    out_dir: Option<PathBuf>,
+
+    /// Sets input format: text, ssml, markdown or html
+    ///
+    /// If not specified, the format is inferred from the extension of the
+    /// input file, defaulting to plain text. Only the readable text of
+    /// Markdown and HTML documents is synthesized, with each heading ending
+    /// a sentence and a paragraph; code blocks are skipped unless
+    /// --code-phrase is given.
+    #[structopt(long = "input-format", parse(try_from_str), raw(global = "true"))]
+    input_format: Option<InputFormat>,
+
+    /// Reads this phrase in place of each code block in Markdown and HTML input, e.g. "Code sample."
+    #[structopt(long = "code-phrase", raw(global = "true"))]
+    code_phrase: Option<String>,
+
+    /// Sets output format: wav, flac, opus, mp3 or pcm
+    ///
+    /// If not specified, the format is inferred from the extension of the
+    /// output file, defaulting to WAVE. Opus is written in an Ogg container,
+    /// while pcm is headerless 16-bit little-endian mono audio.
+    #[structopt(long = "format", parse(try_from_str), raw(global = "true"))]
+    format: Option<OutputFormat>,
+
+    /// Sets flite voice; see --list-voices for the available ones
+    #[structopt(long = "voice", raw(global = "true"))]
+    voice: Option<String>,
+
+    /// Sets speaking rate relative to normal, from 0.25 to 4.0; e.g. 0.8 is 20% slower
+    #[structopt(long = "rate", default_value = "1.0", raw(global = "true"))]
+    rate: f64,
 
-    /// Output WAV file
-    #[structopt(parse(from_os_str))]
-    output: PathBuf,
+    /// Sets pitch relative to the voice's default, from 0.5 to 2.0
+    #[structopt(long = "pitch", default_value = "1.0", raw(global = "true"))]
+    pitch: f64,
 
-    /// Sets number of Golem subtasks
-    #[structopt(long = "subtasks", default_value = "6")]
-    subtasks: u64,
+    /// Sets volume relative to the voice's default, from 0.0 to 4.0
+    #[structopt(long = "volume", default_value = "1.0", raw(global = "true"))]
+    volume: f64,
+
+    /// Lists voices available in the embedded flite build and exits
+    #[structopt(long = "list-voices", raw(global = "true"))]
+    list_voices: bool,
+
+    /// Adds the text normalization rules in this file; may be given more than once
+    ///
+    /// Each line of the file holds a rule of the form `pattern => replacement`,
+    /// where the pattern is a regular expression and the replacement may refer
+    /// to its groups as $1, $2 and so on. Empty lines and lines starting with
+    /// # are skipped. The rules are applied in order, before the built-in ones.
+    #[structopt(
+        long = "rules",
+        parse(from_os_str),
+        number_of_values = 1,
+        raw(global = "true")
+    )]
+    rules: Vec<PathBuf>,
+
+    /// Leaves out the built-in text normalization rules
+    ///
+    /// By default, email addresses, URLs, prices, ISO dates, version numbers,
+    /// Roman numerals and units are spelled out before the text is synthesized,
+    /// as flite reads them out poorly.
+    #[structopt(long = "no-builtin-rules", raw(global = "true"))]
+    no_builtin_rules: bool,
+
+    /// Sets custom pronunciations of words from this file; may be given more than once
+    ///
+    /// Each line of the file either respells a word, as in `Golem = goal em`, or
+    /// gives its phonemes in the syntax of flite's lexicon addenda, as in
+    /// `gwasm : g w aa1 z ah0 m`. Empty lines and lines starting with # are
+    /// skipped. Later entries for a word replace earlier ones.
+    #[structopt(
+        long = "lexicon",
+        parse(from_os_str),
+        number_of_values = 1,
+        raw(global = "true")
+    )]
+    lexicon: Vec<PathBuf>,
+
+    /// Prints the input text the way it would be synthesized, after normalization, then exits
+    #[structopt(
+        long = "print-normalized",
+        raw(conflicts_with_all = r#"&["resume", "dry_run"]"#),
+        raw(global = "true")
+    )]
+    print_normalized: bool,
+
+    /// Sets number of Golem subtasks [default: 6]
+    ///
+    /// Each paragraph gets a subtask of its own though, so that the
+    /// paragraph pause can follow it, unless --max-subtasks caps them.
+    #[structopt(long = "subtasks", raw(global = "true"))]
+    subtasks: Option<usize>,
+
+    /// Sets number of subtasks so that each chunk has about this many words
+    #[structopt(
+        long = "chunk-words",
+        raw(conflicts_with_all = r#"&["subtasks", "chunk_seconds"]"#),
+        raw(global = "true")
+    )]
+    chunk_words: Option<usize>,
+
+    /// Sets number of subtasks so that each chunk takes about this many seconds to speak
+    ///
+    /// The length of the speech is estimated from the word count and the
+    /// speaking rate.
+    #[structopt(
+        long = "chunk-seconds",
+        conflicts_with = "subtasks",
+        raw(global = "true")
+    )]
+    chunk_seconds: Option<f64>,
+
+    /// Caps the number of subtasks, including those the paragraphs get of their own
+    ///
+    /// Past the cap, paragraphs share subtasks, and the paragraph pause only
+    /// follows those which end one.
+    #[structopt(long = "max-subtasks", raw(global = "true"))]
+    max_subtasks: Option<usize>,
+
+    /// Sets what chunks are balanced by: words or duration
+    ///
+    /// With words (the default), each chunk has about the same number of
+    /// words. With duration, each chunk takes about the same time to speak,
+    /// estimated from the syllables of each word with numbers read out, so
+    /// that no subtask takes much longer to compute than the others.
+    #[structopt(
+        long = "balance",
+        parse(try_from_str),
+        default_value = "words",
+        raw(global = "true")
+    )]
+    balance: Balance,
+
+    /// Sets how many times failed subtasks are resubmitted before giving up
+    #[structopt(long = "max-retries", default_value = "2", raw(global = "true"))]
+    max_retries: u32,
+
+    /// Sets length of silence inserted after each paragraph, e.g. 600ms
+    ///
+    /// Each paragraph is synthesized in chunks of its own, which the pause
+    /// is inserted after.
+    #[structopt(
+        long = "paragraph-pause",
+        parse(try_from_str = "humantime::parse_duration"),
+        default_value = "600ms",
+        raw(global = "true")
+    )]
+    paragraph_pause: Duration,
+
+    /// Sets length of silence inserted between chunks within a paragraph, e.g. 150ms
+    #[structopt(
+        long = "chunk-pause",
+        parse(try_from_str = "humantime::parse_duration"),
+        default_value = "0ms",
+        raw(global = "true")
+    )]
+    chunk_pause: Duration,
+
+    /// Trims leading and trailing silence flite emits for each chunk
+    #[structopt(long = "trim-silence", raw(global = "true"))]
+    trim_silence: bool,
+
+    /// Cuts chunks at the nearest zero crossing to avoid clicks at the seams
+    #[structopt(long = "align-seams", raw(global = "true"))]
+    align_seams: bool,
+
+    /// Sets length of the crossfade between consecutive chunks, e.g. 20ms
+    ///
+    /// Where a pause is inserted between two chunks, the chunks are faded
+    /// out and in, respectively, instead.
+    #[structopt(
+        long = "crossfade",
+        parse(try_from_str = "humantime::parse_duration"),
+        default_value = "0ms",
+        raw(global = "true")
+    )]
+    crossfade: Duration,
 
     /// Sets bid value for Golem task
-    #[structopt(long = "bid", default_value = "1.0")]
+    #[structopt(long = "bid", default_value = "1.0", raw(global = "true"))]
     bid: f64,
 
     /// Sets budget value for Golem task
-    #[structopt(long = "budget")]
+    #[structopt(long = "budget", raw(global = "true"))]
     budget: Option<f64>,
 
     /// Sets Golem's task timeout value
-    #[structopt(long = "task_timeout", parse(try_from_str), default_value = "00:10:00")]
+    #[structopt(
+        long = "task_timeout",
+        parse(try_from_str),
+        default_value = "00:10:00",
+        raw(global = "true")
+    )]
     task_timeout: Timeout,
 
     /// Sets Golem's subtask timeout value
     #[structopt(
         long = "subtask_timeout",
         parse(try_from_str),
-        default_value = "00:01:00"
+        default_value = "00:01:00",
+        raw(global = "true")
     )]
     subtask_timeout: Timeout,
 
     /// Sets path to Golem datadir
-    #[structopt(long = "datadir", parse(from_os_str))]
+    #[structopt(long = "datadir", parse(from_os_str), raw(global = "true"))]
     datadir: Option<PathBuf>,
 
     /// Sets RPC address to Golem instance
-    #[structopt(long = "address", default_value = "127.0.0.1")]
+    #[structopt(long = "address", default_value = "127.0.0.1", raw(global = "true"))]
     address: String,
 
     /// Sets RPC port to Golem instance
-    #[structopt(long = "port", default_value = "61000")]
+    #[structopt(long = "port", default_value = "61000", raw(global = "true"))]
     port: u16,
 
     /// Sets workspace dir
@@ -65,26 +329,181 @@ struct Opt {
     /// the entire gWasm task will be stored. Note that it will *not* be
     /// automatically removed after the app finishes successfully; instead,
     /// it is your responsibility to clean up after yourself.
-    #[structopt(long = "workspace", parse(from_os_str))]
+    #[structopt(long = "workspace", parse(from_os_str), raw(global = "true"))]
     workspace: Option<PathBuf>,
 
+    /// Resumes an interrupted or failed run from its workspace dir
+    ///
+    /// Only the chunks which haven't been computed yet are resubmitted; the
+    /// input, output and seam settings are taken from the earlier run. To be
+    /// able to resume a run, it has to be started with --workspace.
+    #[structopt(
+        long = "resume",
+        parse(from_os_str),
+        raw(conflicts_with_all = r#"&["input", "output", "out_dir", "workspace"]"#),
+        raw(global = "true")
+    )]
+    resume: Option<PathBuf>,
+
+    /// Prints how the input would be split and what computing it would cost, then exits
+    ///
+    /// Shows the chunks with their word and character counts and estimated
+    /// length of audio, and the cost projected from the bid, the subtask
+    /// timeout, the number of subtasks and the number of providers gWasm
+    /// has compute each subtask. Nothing is sent to Golem.
+    #[structopt(long = "dry-run", conflicts_with = "resume", raw(global = "true"))]
+    dry_run: bool,
+
+    /// Synthesizes every chunk anew instead of reusing chunks from earlier runs
+    ///
+    /// By default, synthesized chunks are kept in your user cache dir, and
+    /// chunks with the same text and flite options are taken from there
+    /// rather than computed again.
+    #[structopt(long = "no-cache", raw(global = "true"))]
+    no_cache: bool,
+
+    /// Sets the size the cache of synthesized chunks is kept within, in MiB [default: 1 GiB]
+    ///
+    /// Once the cache grows past it, the chunks used least recently are
+    /// removed from it.
+    #[structopt(long = "cache-size", raw(global = "true"))]
+    cache_size: Option<u64>,
+
     /// Turns verbose logging on
-    #[structopt(short = "v", long = "verbose")]
+    #[structopt(short = "v", long = "verbose", raw(global = "true"))]
     verbose: bool,
 
     /// Configures golem-client to use mainnet datadir
-    #[structopt(long)]
+    #[structopt(long, raw(global = "true"))]
     mainnet: bool,
+
+    /// Sets execution backend: golem, local or mock
+    ///
+    /// The golem backend (the default) computes the subtasks on Golem Network.
+    /// The local backend executes the embedded flite WASM binary in-process,
+    /// one subtask at a time, and requires no Golem node. The mock backend
+    /// produces silence instead of speech and is only useful for testing.
+    #[structopt(long = "backend", parse(try_from_str), raw(global = "true"))]
+    backend: Option<BackendKind>,
+
+    /// Synthesizes speech on this machine; shorthand for `--backend local`
+    #[structopt(long, conflicts_with = "backend", raw(global = "true"))]
+    local: bool,
 }
 
-fn main() {
-    let opt = Opt::from_args();
+fn list_voices() {
+    println!("Voices available in the embedded flite build:");
+    for (name, description) in flite::VOICES {
+        println!("  {:<10}{}", name, description);
+    }
+}
+
+/// Takes the options of the subcommand `name` from `matches`, whether they
+/// were given before or after it
+fn subcommand_opt(matches: &ArgMatches, name: &str) -> Opt {
+    // clap only hands subcommands the global options given before them
+    Opt::from_clap(matches.subcommand_matches(name).unwrap())
+}
+
+fn exit_with_error(e: anyhow::Error) -> ! {
+    eprintln!("{}", format!("An error occurred: {:#}", e).red());
+    process::exit(1);
+}
+
+/// Exit code of a cancelled run, following the shell convention for SIGINT
+const CANCELLED_EXIT_CODE: i32 = 130;
+
+/// Cancels the subtasks in flight on the first Ctrl-C, keeping the chunks
+/// done so far; at any other time, or on a second press, exits right away
+fn handle_ctrl_c(token: CancelToken) -> anyhow::Result<()> {
+    ctrlc::set_handler(move || {
+        if token.cancel() {
+            eprintln!("Cancelling the subtasks in flight; press Ctrl-C again to quit right away");
+        } else {
+            process::exit(CANCELLED_EXIT_CODE);
+        }
+    })
+    .context("installing Ctrl-C handler")
+}
+
+fn init_logging() {
+    Builder::from_env(Env::default().default_filter_or("info")).init();
+}
+
+fn run(opt: Opt) -> anyhow::Result<()> {
+    if opt.list_voices {
+        list_voices();
+        return Ok(());
+    }
 
     if opt.verbose {
-        Builder::from_env(Env::default().default_filter_or("info")).init();
+        init_logging();
     }
 
-    if let Err(e) = opt.try_into().and_then(|app: App| app.run()) {
-        eprintln!("{}", format!("An error occurred: {}", e).red())
+    let mut app: App = opt.try_into()?;
+    handle_ctrl_c(app.cancel_token())?;
+    match app.run() {
+        Err(_) if app.is_cancelled() => {
+            let workspace = app.keep_workspace();
+            eprintln!(
+                "{}",
+                format!(
+                    "Cancelled. The workspace has been kept in '{}'; run again with --resume '{}' to pick up where you left off.",
+                    workspace.display(),
+                    workspace.display()
+                )
+                .yellow()
+            );
+            process::exit(CANCELLED_EXIT_CODE);
+        }
+        result => result,
+    }
+}
+
+fn main() {
+    let matches = Cli::clap().get_matches();
+    let Cli { opt, command } = Cli::from_clap(&matches);
+
+    let result = match command {
+        None => run(opt),
+        Some(Command::Submit { input, output }) => {
+            let opt = Opt {
+                input: Some(input),
+                output,
+                ..subcommand_opt(&matches, "submit")
+            };
+            if opt.verbose {
+                init_logging();
+            }
+            detached::submit(opt)
+        }
+        Some(Command::Batch { input }) => {
+            let opt = Opt {
+                input: Some(input),
+                ..subcommand_opt(&matches, "batch")
+            };
+            if opt.out_dir.is_none() {
+                exit_with_error(anyhow!("The batch command requires --out-dir"));
+            }
+            run(opt)
+        }
+        Some(Command::Status { id }) => {
+            let opt = subcommand_opt(&matches, "status");
+            if opt.verbose {
+                init_logging();
+            }
+            detached::status(&id, &opt)
+        }
+        Some(Command::Fetch { id }) => {
+            let opt = subcommand_opt(&matches, "fetch");
+            if opt.verbose {
+                init_logging();
+            }
+            detached::fetch(&id, opt)
+        }
+    };
+
+    if let Err(e) = result {
+        exit_with_error(e);
     }
 }
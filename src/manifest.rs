@@ -0,0 +1,278 @@
+//! Task state persisted in the workspace, so that an interrupted or failed
+//! run can be resumed without recomputing the chunks that already finished.
+//!
+//! The manifest is written once, when the task is prepared. A chunk counts
+//! as done once its result is in the workspace, so finishing one never
+//! rewrites the manifest.
+
+use super::audio::SeamOptions;
+use super::backend::{Subtask, Task};
+use super::output::OutputFormat;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const MANIFEST_FILENAME: &str = "g_flite.json";
+const RESULTS_DIR: &str = "results";
+/// Bumped whenever the layout of the manifest changes incompatibly
+const MANIFEST_VERSION: u32 = 2;
+
+/// A single chunk of the input, together with the state of its computation
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub subtask: Subtask,
+    /// Silence inserted after the chunk in the combined output
+    pub pause: Duration,
+    /// Whether the result of the chunk is in the workspace; worked out when
+    /// the manifest is loaded
+    #[serde(skip)]
+    pub done: bool,
+}
+
+/// A chapter of a book, marked in the output of its document
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Chapter {
+    pub title: String,
+    /// Index of the first chunk of the chapter within its document
+    pub start: usize,
+}
+
+/// An input document, whose chunks make up a consecutive run of entries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Document {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    pub entries: Range<usize>,
+    /// Title of the book the document is made of, if it is one
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub chapters: Vec<Chapter>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    version: u32,
+    pub documents: Vec<Document>,
+    pub output_format: OutputFormat,
+    pub seams: SeamOptions,
+    pub entries: Vec<Entry>,
+}
+
+impl Manifest {
+    pub fn new(output_format: OutputFormat, seams: SeamOptions) -> Self {
+        Self {
+            version: MANIFEST_VERSION,
+            documents: Vec::new(),
+            output_format,
+            seams,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Appends a document split into the subtasks of `task`, each followed
+    /// by the respective pause; a book also comes with its title and
+    /// chapters
+    pub fn add_document(
+        &mut self,
+        input: PathBuf,
+        output: PathBuf,
+        task: Task,
+        pauses: &[Duration],
+        title: Option<String>,
+        chapters: Vec<Chapter>,
+    ) {
+        let start = self.entries.len();
+        self.entries.extend(
+            task.subtasks
+                .into_iter()
+                .zip(pauses)
+                .map(|(subtask, &pause)| Entry {
+                    subtask,
+                    pause,
+                    done: false,
+                }),
+        );
+
+        self.documents.push(Document {
+            input,
+            output,
+            entries: start..self.entries.len(),
+            title,
+            chapters,
+        });
+    }
+
+    pub fn path(workspace: &Path) -> PathBuf {
+        workspace.join(MANIFEST_FILENAME)
+    }
+
+    pub fn result_path(workspace: &Path, i: usize) -> PathBuf {
+        workspace.join(RESULTS_DIR).join(format!("chunk_{}.wav", i))
+    }
+
+    pub fn load(workspace: &Path) -> Result<Self> {
+        let path = Self::path(workspace);
+        let contents = fs::read(&path)
+            .with_context(|| format!("reading task manifest '{}'", path.display()))?;
+        let mut manifest: Self = serde_json::from_slice(&contents)
+            .with_context(|| format!("parsing task manifest '{}'", path.display()))?;
+
+        if manifest.version != MANIFEST_VERSION {
+            bail!(
+                "task manifest '{}' has version {}, but this version of g_flite expects {}",
+                path.display(),
+                manifest.version,
+                MANIFEST_VERSION
+            );
+        }
+
+        for (i, entry) in manifest.entries.iter_mut().enumerate() {
+            entry.done = Self::result_path(workspace, i).is_file();
+        }
+
+        Ok(manifest)
+    }
+
+    /// Writes the manifest of a new task to the workspace, dropping any
+    /// results left behind by an earlier one
+    pub fn save(&self, workspace: &Path) -> Result<()> {
+        let results = workspace.join(RESULTS_DIR);
+        if results.exists() {
+            fs::remove_dir_all(&results)
+                .with_context(|| format!("removing stale results in '{}'", results.display()))?;
+        }
+
+        let path = Self::path(workspace);
+        let contents = serde_json::to_vec_pretty(self).context("serializing task manifest")?;
+
+        // write to a temporary file first, so that an interruption never
+        // leaves a truncated manifest behind
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, contents)
+            .and_then(|_| fs::rename(&tmp, &path))
+            .with_context(|| format!("writing task manifest '{}'", path.display()))
+    }
+
+    /// Indices of the chunks that still need computing
+    pub fn pending(&self) -> Vec<usize> {
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| !entry.done)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Task made of the chunks at `indices`, in that order
+    pub fn task(&self, indices: &[usize]) -> Task {
+        Task {
+            subtasks: indices
+                .iter()
+                .map(|&i| self.entries[i].subtask.clone())
+                .collect(),
+        }
+    }
+
+    pub fn pauses(&self, document: &Document) -> Vec<Duration> {
+        self.entries[document.entries.clone()]
+            .iter()
+            .map(|entry| entry.pause)
+            .collect()
+    }
+
+    /// Stores the output of chunk `i` in the workspace and marks it as done
+    pub fn save_result(&mut self, workspace: &Path, i: usize, output: &[u8]) -> Result<()> {
+        let path = Self::result_path(workspace, i);
+        // the result only appears once it is complete, as it marks the
+        // chunk as done for a resumed run
+        let tmp = path.with_extension("wav.tmp");
+        fs::create_dir_all(workspace.join(RESULTS_DIR))
+            .and_then(|_| fs::write(&tmp, output))
+            .and_then(|_| fs::rename(&tmp, &path))
+            .with_context(|| format!("writing result of chunk '{}' to '{}'", i, path.display()))?;
+
+        self.entries[i].done = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(num_chunks: usize) -> Manifest {
+        let subtasks = (0..num_chunks)
+            .map(|i| Subtask {
+                text: format!("Chunk {}.", i),
+                args: Vec::new(),
+                lexicon: None,
+                volume: None,
+            })
+            .collect();
+        let mut manifest = Manifest::new(OutputFormat::Wav, SeamOptions::default());
+        manifest.add_document(
+            PathBuf::from("in.txt"),
+            PathBuf::from("out.wav"),
+            Task { subtasks },
+            &vec![Duration::from_millis(200); num_chunks],
+            None,
+            Vec::new(),
+        );
+        manifest
+    }
+
+    #[test]
+    fn resumes_with_the_chunks_left() {
+        let workspace = tempfile::tempdir().unwrap();
+        let mut saved = manifest(3);
+        saved.save(workspace.path()).unwrap();
+        saved.save_result(workspace.path(), 1, b"RIFF").unwrap();
+
+        let loaded = Manifest::load(workspace.path()).unwrap();
+        assert_eq!(loaded.pending(), vec![0, 2]);
+        assert_eq!(loaded.entries[2].subtask, saved.entries[2].subtask);
+        assert_eq!(loaded.pauses(&loaded.documents[0]).len(), 3);
+    }
+
+    #[test]
+    fn ignores_unfinished_results() {
+        let workspace = tempfile::tempdir().unwrap();
+        manifest(2).save(workspace.path()).unwrap();
+        let tmp = Manifest::result_path(workspace.path(), 0).with_extension("wav.tmp");
+        fs::create_dir_all(tmp.parent().unwrap()).unwrap();
+        fs::write(&tmp, b"RI").unwrap();
+
+        assert_eq!(
+            Manifest::load(workspace.path()).unwrap().pending(),
+            vec![0, 1]
+        );
+    }
+
+    #[test]
+    fn new_task_drops_stale_results() {
+        let workspace = tempfile::tempdir().unwrap();
+        let mut old = manifest(2);
+        old.save(workspace.path()).unwrap();
+        old.save_result(workspace.path(), 0, b"RIFF").unwrap();
+
+        manifest(2).save(workspace.path()).unwrap();
+        assert_eq!(
+            Manifest::load(workspace.path()).unwrap().pending(),
+            vec![0, 1]
+        );
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let workspace = tempfile::tempdir().unwrap();
+        let mut manifest = manifest(1);
+        manifest.version = MANIFEST_VERSION + 1;
+        manifest.save(workspace.path()).unwrap();
+
+        assert!(Manifest::load(workspace.path()).is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! Reading of Markdown input.
+//!
+//! Only the text a reader would see is kept: emphasis markers, link
+//! destinations, images and raw HTML are dropped. Headings and list items
+//! end a sentence, so that flite pauses after them, and each heading makes
+//! up a segment of its own, which the paragraph pause follows. Code blocks are skipped or replaced with a
+//! phrase, since reading out source code is of little use to a listener.
+
+use super::input::Prose;
+use super::split::Segment;
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+/// Parses a Markdown document into segments; see [`super::input::read`]
+pub fn parse(contents: &str, code_phrase: Option<&str>) -> Vec<Segment> {
+    let mut prose = Prose::default();
+    // nesting depth of the elements whose text is not read out
+    let mut skipped = 0;
+
+    for event in Parser::new_ext(contents, Options::ENABLE_TABLES) {
+        match event {
+            Event::Start(Tag::CodeBlock(_)) | Event::Start(Tag::Image(..)) => skipped += 1,
+            Event::End(Tag::CodeBlock(_)) => {
+                skipped -= 1;
+                match code_phrase {
+                    Some(phrase) => prose.push_paragraph(phrase),
+                    None => prose.end_paragraph(),
+                }
+            }
+            Event::End(Tag::Image(..)) => skipped -= 1,
+            _ if skipped > 0 => {}
+
+            Event::Start(Tag::Heading(_)) => prose.start_heading(),
+            Event::End(Tag::Heading(_)) => prose.end_heading(),
+            Event::End(Tag::Paragraph)
+            | Event::End(Tag::BlockQuote)
+            | Event::End(Tag::List(_))
+            | Event::End(Tag::Table(_))
+            | Event::Rule => prose.end_paragraph(),
+            Event::End(Tag::Item) | Event::End(Tag::TableHead) | Event::End(Tag::TableRow) => {
+                prose.end_sentence()
+            }
+            Event::End(Tag::TableCell) | Event::SoftBreak | Event::HardBreak => prose.space(),
+            Event::Text(text) | Event::Code(text) => prose.push(&text),
+            _ => {}
+        }
+    }
+
+    prose.into_segments()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(contents: &str, code_phrase: Option<&str>) -> String {
+        parse(contents, code_phrase)
+            .into_iter()
+            .map(|segment| segment.text)
+            .collect::<String>()
+            .trim_end()
+            .to_string()
+    }
+
+    #[test]
+    fn keeps_the_text_a_reader_sees() {
+        assert_eq!(
+            text(
+                "See [the *docs*](https://example.com) ![logo](logo.png) and `cargo`.\n",
+                None
+            ),
+            "See the docs and cargo."
+        );
+    }
+
+    #[test]
+    fn headings_and_list_items_end_sentences() {
+        assert_eq!(
+            text("# Getting started\nSome text\n\n- one\n- two\n", None),
+            "Getting started.\n\nSome text\n\none. two."
+        );
+    }
+
+    #[test]
+    fn headings_make_up_segments_of_their_own() {
+        let segments = parse("# One\nText\n\n## Two\nMore text\n", None);
+        let texts: Vec<&str> = segments.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, ["One.\n\n", "Text\n\n", "Two.\n\n", "More text\n\n"]);
+    }
+
+    #[test]
+    fn code_blocks() {
+        let contents = "Before\n\n```\nlet x = 1;\n```\n\nAfter\n";
+        assert_eq!(text(contents, None), "Before\n\nAfter");
+        assert_eq!(
+            text(contents, Some("Code sample.")),
+            "Before\n\nCode sample.\n\nAfter"
+        );
+    }
+}
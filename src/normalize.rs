@@ -0,0 +1,660 @@
+//! Normalization of the input text before it is synthesized.
+//!
+//! flite expands numbers and abbreviations on its own, but reads out things
+//! like URLs, email addresses, prices or version numbers character by
+//! character, or not at all. The [`Normalizer`] rewrites those into words
+//! first, with a set of regex rules, each replacing the matches of its
+//! pattern. Besides the built-in rules, rules can be loaded from files of
+//! lines of the form `pattern => replacement`.
+
+use anyhow::{anyhow, Context, Result};
+use regex::{Captures, Regex};
+use std::fs;
+use std::path::Path;
+
+/// Separates the pattern from the replacement in a rule file
+const RULE_SEPARATOR: &str = "=>";
+
+const ONES: [&str; 20] = [
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+
+const TENS: [&str; 10] = [
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+/// Units spoken out after a number, in singular and plural
+///
+/// The abbreviations are matched case-sensitively, so that e.g. the "G" of
+/// "5G" or the "M" of "100M" aren't taken for grams or meters. Single-letter
+/// ones also need a space after the number, as "5m" may as well mean five
+/// minutes or million.
+const UNITS: &[(&str, &str, &str)] = &[
+    ("km/h", "kilometer per hour", "kilometers per hour"),
+    ("mph", "mile per hour", "miles per hour"),
+    ("km", "kilometer", "kilometers"),
+    ("cm", "centimeter", "centimeters"),
+    ("mm", "millimeter", "millimeters"),
+    ("m", "meter", "meters"),
+    ("kg", "kilogram", "kilograms"),
+    ("mg", "milligram", "milligrams"),
+    ("g", "gram", "grams"),
+    ("ml", "milliliter", "milliliters"),
+    ("mL", "milliliter", "milliliters"),
+    ("l", "liter", "liters"),
+    ("L", "liter", "liters"),
+    ("kB", "kilobyte", "kilobytes"),
+    ("KB", "kilobyte", "kilobytes"),
+    ("MB", "megabyte", "megabytes"),
+    ("GB", "gigabyte", "gigabytes"),
+    ("TB", "terabyte", "terabytes"),
+    ("Hz", "hertz", "hertz"),
+    ("kHz", "kilohertz", "kilohertz"),
+    ("MHz", "megahertz", "megahertz"),
+    ("GHz", "gigahertz", "gigahertz"),
+];
+
+/// Names of monarchs and popes, which a Roman numeral after is read as an
+/// ordinal, e.g. "Henry VIII" as "Henry the eighth"
+const REGNAL_NAMES: &[&str] = &[
+    "Alexander",
+    "Alfonso",
+    "Amenhotep",
+    "Anne",
+    "Benedict",
+    "Boniface",
+    "Carlos",
+    "Catherine",
+    "Charles",
+    "Christian",
+    "Clement",
+    "Constantine",
+    "David",
+    "Edward",
+    "Elizabeth",
+    "Ferdinand",
+    "Francis",
+    "Frederick",
+    "Friedrich",
+    "George",
+    "Gregory",
+    "Gustav",
+    "Haakon",
+    "Henry",
+    "Innocent",
+    "Ivan",
+    "James",
+    "John",
+    "Joseph",
+    "Juan",
+    "Leo",
+    "Leopold",
+    "Louis",
+    "Ludwig",
+    "Malcolm",
+    "Mary",
+    "Napoleon",
+    "Nicholas",
+    "Olaf",
+    "Otto",
+    "Paul",
+    "Pedro",
+    "Peter",
+    "Philip",
+    "Pius",
+    "Ramesses",
+    "Richard",
+    "Robert",
+    "Rudolf",
+    "Sixtus",
+    "Stephen",
+    "Urban",
+    "Victor",
+    "Wilhelm",
+    "William",
+];
+
+/// Currency symbols with the names of the currency and its hundredth, in
+/// singular and plural
+const CURRENCIES: &[(&str, [&str; 4])] = &[
+    ("$", ["dollar", "dollars", "cent", "cents"]),
+    ("€", ["euro", "euros", "cent", "cents"]),
+    ("£", ["pound", "pounds", "penny", "pence"]),
+];
+
+/// Spells out a number in words, e.g. 42 as "forty-two"
+fn cardinal(n: u32) -> String {
+    let (head, scale, rest) = match n {
+        0..=19 => return ONES[n as usize].to_string(),
+        20..=99 => {
+            let tens = TENS[(n / 10) as usize];
+            return match n % 10 {
+                0 => tens.to_string(),
+                ones => format!("{}-{}", tens, ONES[ones as usize]),
+            };
+        }
+        100..=999 => (n / 100, "hundred", n % 100),
+        1_000..=999_999 => (n / 1_000, "thousand", n % 1_000),
+        _ => (n / 1_000_000, "million", n % 1_000_000),
+    };
+
+    match rest {
+        0 => format!("{} {}", cardinal(head), scale),
+        rest => format!("{} {} {}", cardinal(head), scale, cardinal(rest)),
+    }
+}
+
+/// Spells out an ordinal number in words, e.g. 42 as "forty-second"
+fn ordinal(n: u32) -> String {
+    let words = cardinal(n);
+    let start = words.rfind([' ', '-']).map_or(0, |i| i + 1);
+    let (head, last) = words.split_at(start);
+
+    let last = match last {
+        "one" => "first".to_string(),
+        "two" => "second".to_string(),
+        "three" => "third".to_string(),
+        "five" => "fifth".to_string(),
+        "eight" => "eighth".to_string(),
+        "nine" => "ninth".to_string(),
+        "twelve" => "twelfth".to_string(),
+        last if last.ends_with('y') => format!("{}ieth", &last[..last.len() - 1]),
+        last => format!("{}th", last),
+    };
+
+    format!("{}{}", head, last)
+}
+
+fn to_roman(mut n: u32) -> String {
+    const NUMERALS: [(u32, &str); 13] = [
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+
+    let mut roman = String::new();
+    for &(value, numeral) in NUMERALS.iter() {
+        while n >= value {
+            roman.push_str(numeral);
+            n -= value;
+        }
+    }
+
+    roman
+}
+
+/// Parses a Roman numeral, provided it is written the canonical way, so
+/// that words which merely consist of the same letters are left alone
+fn parse_roman(numeral: &str) -> Option<u32> {
+    let values = numeral
+        .chars()
+        .map(|c| match c {
+            'I' => Some(1),
+            'V' => Some(5),
+            'X' => Some(10),
+            'L' => Some(50),
+            'C' => Some(100),
+            'D' => Some(500),
+            'M' => Some(1000),
+            _ => None,
+        })
+        .collect::<Option<Vec<u32>>>()?;
+
+    let mut n = 0;
+    for (i, &value) in values.iter().enumerate() {
+        match values.get(i + 1) {
+            Some(&next) if next > value => n -= value as i64,
+            _ => n += value as i64,
+        }
+    }
+
+    let n = n as u32;
+    Some(n).filter(|&n| n > 0 && to_roman(n) == numeral)
+}
+
+/// Spells out the dots, at signs and the like of an address
+fn spell_address(address: &str) -> String {
+    address
+        .replace('.', " dot ")
+        .replace('@', " at ")
+        .replace('_', " underscore ")
+}
+
+fn replace_email(caps: &Captures) -> String {
+    spell_address(&caps[0])
+}
+
+/// Reads out the domain of a URL, leaving out the scheme and the path
+fn replace_url(caps: &Captures) -> String {
+    spell_address(&caps[1])
+}
+
+fn remove_thousands_separators(caps: &Captures) -> String {
+    caps[0].replace(',', "")
+}
+
+fn replace_currency(caps: &Captures) -> String {
+    let names = CURRENCIES
+        .iter()
+        .find(|(symbol, _)| *symbol == &caps[1])
+        .map(|(_, names)| names)
+        .unwrap();
+    let amount = &caps[2];
+    let plural = |n: &str, singular: usize| {
+        if n == "1" {
+            names[singular]
+        } else {
+            names[singular + 1]
+        }
+    };
+
+    match (caps.get(3), caps.get(4)) {
+        (fraction, Some(scale)) => {
+            let fraction = fraction.map_or("", |fraction| fraction.as_str());
+            format!("{}{} {} {}", amount, fraction, scale.as_str(), names[1])
+        }
+        (Some(fraction), None) if fraction.as_str().len() == 3 => {
+            let cents = fraction.as_str()[1..].trim_start_matches('0');
+            match cents {
+                "" => format!("{} {}", amount, plural(amount, 0)),
+                cents => format!(
+                    "{} {} and {} {}",
+                    amount,
+                    plural(amount, 0),
+                    cents,
+                    plural(cents, 2)
+                ),
+            }
+        }
+        (Some(fraction), None) => format!("{}{} {}", amount, fraction.as_str(), names[1]),
+        (None, None) => format!("{} {}", amount, plural(amount, 0)),
+    }
+}
+
+fn replace_date(caps: &Captures) -> String {
+    let month: usize = caps[2].parse().unwrap();
+    let day: u32 = caps[3].parse().unwrap();
+
+    format!("{} {}, {}", MONTHS[month - 1], ordinal(day), &caps[1])
+}
+
+fn replace_version(caps: &Captures) -> String {
+    let prefix = if caps.get(1).is_some() {
+        "version "
+    } else {
+        ""
+    };
+
+    format!("{}{}", prefix, caps[2].replace('.', " point "))
+}
+
+fn replace_numbered_roman(caps: &Captures) -> String {
+    match parse_roman(&caps[2]) {
+        Some(n) => format!("{} {}", &caps[1], n),
+        None => caps[0].to_string(),
+    }
+}
+
+fn replace_regnal_roman(caps: &Captures) -> String {
+    match parse_roman(&caps[2]) {
+        Some(n) => format!("{} the {}", &caps[1], ordinal(n)),
+        None => caps[0].to_string(),
+    }
+}
+
+fn replace_unit(caps: &Captures) -> String {
+    let number = &caps[1];
+    let unit = caps.get(2).or_else(|| caps.get(3)).unwrap().as_str();
+    let (_, singular, plural) = UNITS
+        .iter()
+        .find(|(abbreviation, _, _)| *abbreviation == unit)
+        .unwrap();
+
+    if number == "1" {
+        format!("{} {}", number, singular)
+    } else {
+        format!("{} {}", number, plural)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Replacement {
+    /// Replacement string, which may refer to the groups of the pattern,
+    /// e.g. `$1`
+    Template(String),
+    Function(fn(&Captures) -> String),
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Regex,
+    replacement: Replacement,
+}
+
+impl Rule {
+    fn builtin(pattern: &str, replacement: Replacement) -> Self {
+        Self {
+            pattern: Regex::new(pattern).expect("invalid built-in normalization rule"),
+            replacement,
+        }
+    }
+
+    fn apply(&self, text: &str) -> String {
+        match &self.replacement {
+            Replacement::Template(template) => self
+                .pattern
+                .replace_all(text, template.as_str())
+                .into_owned(),
+            Replacement::Function(function) => {
+                self.pattern.replace_all(text, *function).into_owned()
+            }
+        }
+    }
+}
+
+/// Rewrites text with a sequence of regex rules, applied in the order they
+/// were added
+#[derive(Debug, Clone, Default)]
+pub struct Normalizer {
+    rules: Vec<Rule>,
+}
+
+impl Normalizer {
+    /// Creates a normalizer with just the built-in rules
+    pub fn builtin() -> Self {
+        let mut normalizer = Self::default();
+        normalizer.add_builtin_rules();
+        normalizer
+    }
+
+    /// Adds the built-in rules, which spell out email addresses, URLs,
+    /// prices, ISO dates, version numbers, Roman numerals after a monarch's
+    /// name or words such as "Chapter", and units
+    pub fn add_builtin_rules(&mut self) {
+        use Replacement::{Function, Template};
+
+        let units = |single_letter: bool| {
+            UNITS
+                .iter()
+                .filter(|(abbreviation, _, _)| (abbreviation.len() == 1) == single_letter)
+                .map(|(abbreviation, _, _)| regex::escape(abbreviation))
+                .collect::<Vec<_>>()
+                .join("|")
+        };
+        let regnal_names = REGNAL_NAMES.join("|");
+        let symbols = CURRENCIES
+            .iter()
+            .map(|(symbol, _)| regex::escape(symbol))
+            .collect::<Vec<_>>()
+            .join("|");
+
+        self.rules.extend(vec![
+            Rule::builtin(
+                r"\b[\w.%+-]+@[A-Za-z0-9-]+(\.[A-Za-z0-9-]+)+\b",
+                Function(replace_email),
+            ),
+            Rule::builtin(
+                r#"\b(?:https?://(?:www\.)?|www\.)([A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)+)(?:[/?#]\S*[^\s.,;:!?'")\]]|/)?"#,
+                Function(replace_url),
+            ),
+            Rule::builtin(
+                r"\b\d{1,3}(,\d{3})+\b",
+                Function(remove_thousands_separators),
+            ),
+            Rule::builtin(
+                &format!(
+                    r"({})\s?(\d+)(\.\d+)?(?:\s(thousand|million|billion|trillion)\b)?",
+                    symbols
+                ),
+                Function(replace_currency),
+            ),
+            Rule::builtin(
+                r"\b(\d{4})-(0[1-9]|1[0-2])-(0[1-9]|[12]\d|3[01])\b",
+                Function(replace_date),
+            ),
+            Rule::builtin(
+                r"\b(v)?(\d+\.\d+(?:\.\d+)+)\b",
+                Function(replace_version),
+            ),
+            Rule::builtin(r"\b(v)(\d+\.\d+)\b", Function(replace_version)),
+            Rule::builtin(
+                r"\b(Chapter|Part|Book|Volume|Act|Scene|Section|Appendix|Article|War)\s+([IVXLCDM]+)\b",
+                Function(replace_numbered_roman),
+            ),
+            // a lone "I" is left alone, as it is more likely the pronoun
+            Rule::builtin(
+                &format!(r"\b({})\s+([IVXL]{{2,}}|V|X)\b", regnal_names),
+                Function(replace_regnal_roman),
+            ),
+            Rule::builtin(
+                &format!(
+                    r"\b(\d+(?:\.\d+)?)(?:\s?({})|\s({}))\b",
+                    units(false),
+                    units(true)
+                ),
+                Function(replace_unit),
+            ),
+            Rule::builtin(r"(\d)\s?%", Template("$1 percent".to_string())),
+            Rule::builtin(
+                r"(\d)\s?°\s?C\b",
+                Template("$1 degrees Celsius".to_string()),
+            ),
+            Rule::builtin(
+                r"(\d)\s?°\s?F\b",
+                Template("$1 degrees Fahrenheit".to_string()),
+            ),
+        ]);
+    }
+
+    /// Adds a rule replacing the matches of the regex `pattern` with
+    /// `replacement`, which may refer to groups of the pattern, e.g. `$1`
+    pub fn add_rule(&mut self, pattern: &str, replacement: &str) -> Result<()> {
+        let pattern = Regex::new(pattern).context("parsing rule pattern")?;
+        self.rules.push(Rule {
+            pattern,
+            replacement: Replacement::Template(replacement.to_string()),
+        });
+
+        Ok(())
+    }
+
+    /// Adds the rules in the file at `path`, one `pattern => replacement`
+    /// per line; empty lines and lines starting with `#` are skipped
+    pub fn load_rules(&mut self, path: &Path) -> Result<()> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("reading rule file '{}'", path.display()))?;
+
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            line.split_once(RULE_SEPARATOR)
+                .ok_or_else(|| anyhow!("expected 'pattern {} replacement'", RULE_SEPARATOR))
+                .and_then(|(pattern, replacement)| {
+                    self.add_rule(pattern.trim(), replacement.trim())
+                })
+                .with_context(|| {
+                    format!("parsing line {} of rule file '{}'", i + 1, path.display())
+                })?;
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn normalize(&self, text: &str) -> String {
+        self.rules
+            .iter()
+            .fold(text.to_string(), |text, rule| rule.apply(&text))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normalize(text: &str) -> String {
+        Normalizer::builtin().normalize(text)
+    }
+
+    #[test]
+    fn numbers_in_words() {
+        assert_eq!(cardinal(42), "forty-two");
+        assert_eq!(cardinal(1_905), "one thousand nine hundred five");
+        assert_eq!(ordinal(8), "eighth");
+        assert_eq!(ordinal(20), "twentieth");
+        assert_eq!(ordinal(42), "forty-second");
+    }
+
+    #[test]
+    fn roman_numerals() {
+        assert_eq!(parse_roman("XIV"), Some(14));
+        assert_eq!(parse_roman("MCMXCIV"), Some(1994));
+        // not written the canonical way
+        assert_eq!(parse_roman("IIII"), None);
+        assert_eq!(parse_roman("VX"), None);
+        assert_eq!(parse_roman("LID"), None);
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(
+            normalize("Visit https://www.example.com/docs today."),
+            "Visit example dot com today."
+        );
+        assert_eq!(
+            normalize("Mail john_doe@example.org now."),
+            "Mail john underscore doe at example dot org now."
+        );
+    }
+
+    #[test]
+    fn prices() {
+        assert_eq!(
+            normalize("It costs $1,234.56."),
+            "It costs 1234 dollars and 56 cents."
+        );
+        assert_eq!(normalize("Only €1 or £5"), "Only 1 euro or 5 pounds");
+        assert_eq!(normalize("$2.5 million"), "2.5 million dollars");
+    }
+
+    #[test]
+    fn dates_and_versions() {
+        assert_eq!(normalize("On 2024-03-05."), "On March fifth, 2024.");
+        assert_eq!(
+            normalize("Update to v1.2.3 now"),
+            "Update to version 1 point 2 point 3 now"
+        );
+    }
+
+    #[test]
+    fn roman_numerals_after_words() {
+        assert_eq!(normalize("Chapter IV begins"), "Chapter 4 begins");
+        assert_eq!(
+            normalize("Henry VIII and George V"),
+            "Henry the eighth and George the fifth"
+        );
+    }
+
+    #[test]
+    fn roman_numerals_after_other_names_are_left_alone() {
+        let text = "Type II diabetes in Phase III trials";
+        assert_eq!(normalize(text), text);
+        assert_eq!(
+            normalize("Then Henry I went home"),
+            "Then Henry I went home"
+        );
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!(
+            normalize("He ran 5 km at 12 km/h with 1 kg"),
+            "He ran 5 kilometers at 12 kilometers per hour with 1 kilogram"
+        );
+        assert_eq!(normalize("A 2 m wall"), "A 2 meters wall");
+        assert_eq!(
+            normalize("16 GB at 3.2 GHz"),
+            "16 gigabytes at 3.2 gigahertz"
+        );
+        assert_eq!(normalize("20% at 25°C"), "20 percent at 25 degrees Celsius");
+    }
+
+    #[test]
+    fn unit_lookalikes_are_left_alone() {
+        let text = "Our 5G network and 4G LTE";
+        assert_eq!(normalize(text), text);
+        assert_eq!(normalize("He ran 100M"), "He ran 100M");
+        assert_eq!(normalize("Meet at 5m past"), "Meet at 5m past");
+        assert_eq!(normalize("16 gb of"), "16 gb of");
+    }
+
+    #[test]
+    fn user_rules_come_first() {
+        let mut normalizer = Normalizer::default();
+        normalizer
+            .add_rule(r"\bASAP\b", "as soon as possible")
+            .unwrap();
+        normalizer.add_rule(r"(\d+)x(\d+)", "$1 by $2").unwrap();
+        normalizer.add_builtin_rules();
+
+        assert_eq!(
+            normalizer.normalize("Cut 4x6 cards ASAP"),
+            "Cut 4 by 6 cards as soon as possible"
+        );
+    }
+
+    #[test]
+    fn invalid_user_rule() {
+        assert!(Normalizer::default().add_rule("(", "x").is_err());
+    }
+}
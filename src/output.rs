@@ -0,0 +1,500 @@
+//! Writers encoding the combined samples into output files.
+
+use anyhow::{anyhow, bail, Context, Result};
+use audiopus::coder::Encoder as OpusEncoder;
+use audiopus::{Application, Channels, SampleRate};
+use flac_bound::FlacEncoder;
+use mp3lame_encoder::{Bitrate, FlushNoGap, MonoPcm, Quality};
+use ogg::{PacketWriteEndInfo, PacketWriter};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, Write};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use tempfile::NamedTempFile;
+
+/// Length of a single Opus frame
+const OPUS_FRAME_MILLIS: u32 = 20;
+/// Largest Opus packet we expect the encoder to produce for a single frame
+const OPUS_MAX_PACKET: usize = 4000;
+/// Samples at 48kHz the decoder should drop at the start of the stream; this
+/// is the lookahead of libopus for the VoIP application
+const OPUS_PRE_SKIP: u16 = 312;
+/// Serial number of the only logical stream in the Ogg container
+const OGG_SERIAL: u32 = 1;
+/// Length of the canonical WAVE header
+const WAV_HEADER_LEN: usize = 44;
+/// Value of the RIFF and data chunk lengths of a WAVE stream of unknown length
+const WAV_STREAM_LEN: u32 = u32::MAX;
+/// CUE sheets count time in CD frames
+const CUE_FRAMES_PER_SECOND: u128 = 75;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Wav,
+    Flac,
+    Opus,
+    Mp3,
+    /// Headerless 16-bit little-endian mono samples
+    Pcm,
+}
+
+impl OutputFormat {
+    /// Infers the output format from the file extension, defaulting to WAVE
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("flac") => OutputFormat::Flac,
+            Some("opus") | Some("ogg") => OutputFormat::Opus,
+            Some("mp3") => OutputFormat::Mp3,
+            Some("pcm") | Some("raw") => OutputFormat::Pcm,
+            _ => OutputFormat::Wav,
+        }
+    }
+
+    /// Usual file extension of the format
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Wav => "wav",
+            OutputFormat::Flac => "flac",
+            OutputFormat::Opus => "opus",
+            OutputFormat::Mp3 => "mp3",
+            OutputFormat::Pcm => "pcm",
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "wav" => Ok(OutputFormat::Wav),
+            "flac" => Ok(OutputFormat::Flac),
+            "opus" => Ok(OutputFormat::Opus),
+            "mp3" => Ok(OutputFormat::Mp3),
+            "pcm" => Ok(OutputFormat::Pcm),
+            other => bail!(
+                "unknown output format '{}'; expected one of: wav, flac, opus, mp3, pcm",
+                other
+            ),
+        }
+    }
+}
+
+/// Sink for the combined mono 16-bit samples
+///
+/// Samples are fed in as they become available, so that the whole output
+/// never has to be held in memory at once.
+pub trait Encoder {
+    fn write(&mut self, samples: &[i16]) -> Result<()>;
+
+    /// Flushes any buffered samples and finalizes the output
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+/// Creates an encoder writing the given format into `writer`
+pub fn encoder<'w, W: Write + Seek + 'w>(
+    format: OutputFormat,
+    writer: W,
+    sample_rate: u32,
+) -> Result<Box<dyn Encoder + 'w>> {
+    Ok(match format {
+        OutputFormat::Wav => Box::new(Wav::new(writer, sample_rate)?),
+        OutputFormat::Flac => Box::new(Flac::new(writer, sample_rate)?),
+        OutputFormat::Opus => Box::new(Opus::new(writer, sample_rate)?),
+        OutputFormat::Mp3 => Box::new(Mp3::new(writer, sample_rate)?),
+        OutputFormat::Pcm => Box::new(Pcm(writer)),
+    })
+}
+
+/// Creates an encoder writing the given format into `writer`, which needn't
+/// be seekable, such as a pipe
+///
+/// WAVE is written with the length fields of its header set to their
+/// maximum, as is customary for streams whose length isn't known upfront;
+/// the other formats are written the same way as by [`encoder`].
+pub fn stream<'w, W: Write + 'w>(
+    format: OutputFormat,
+    writer: W,
+    sample_rate: u32,
+) -> Result<Box<dyn Encoder + 'w>> {
+    Ok(match format {
+        OutputFormat::Wav => Box::new(WavStream::new(writer, sample_rate)?),
+        OutputFormat::Flac => Box::new(Flac::new(writer, sample_rate)?),
+        OutputFormat::Opus => Box::new(Opus::new(writer, sample_rate)?),
+        OutputFormat::Mp3 => Box::new(Mp3::new(writer, sample_rate)?),
+        OutputFormat::Pcm => Box::new(Pcm(writer)),
+    })
+}
+
+/// Creates the output file at `path` and an encoder writing into it
+pub fn create(format: OutputFormat, path: &Path, sample_rate: u32) -> Result<Box<dyn Encoder>> {
+    let file = File::create(path)
+        .map(BufWriter::new)
+        .with_context(|| format!("creating output file '{}'", path.display()))?;
+
+    encoder(format, file, sample_rate)
+}
+
+/// Titles in a CUE sheet are quoted, with no way of escaping quotes
+fn cue_string(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "'"))
+}
+
+/// Writes a CUE sheet at `path`, marking the start of each of `tracks`,
+/// given by their title and offset, within the audio file `audio`
+pub fn write_cue_sheet(
+    path: &Path,
+    audio: &Path,
+    format: OutputFormat,
+    title: Option<&str>,
+    tracks: &[(String, Duration)],
+) -> Result<()> {
+    let filename = audio
+        .file_name()
+        .ok_or_else(|| anyhow!("working out the filename of '{}'", audio.display()))?
+        .to_string_lossy();
+    let file_type = match format {
+        OutputFormat::Mp3 => "MP3",
+        OutputFormat::Pcm => "BINARY",
+        _ => "WAVE",
+    };
+
+    let mut sheet = String::new();
+    if let Some(title) = title {
+        writeln!(sheet, "TITLE {}", cue_string(title)).unwrap();
+    }
+    writeln!(sheet, "FILE {} {}", cue_string(&filename), file_type).unwrap();
+    for (i, (title, start)) in tracks.iter().enumerate() {
+        let frames = start.as_millis() * CUE_FRAMES_PER_SECOND / 1000;
+        writeln!(sheet, "  TRACK {:02} AUDIO", i + 1).unwrap();
+        writeln!(sheet, "    TITLE {}", cue_string(title)).unwrap();
+        writeln!(
+            sheet,
+            "    INDEX 01 {:02}:{:02}:{:02}",
+            frames / CUE_FRAMES_PER_SECOND / 60,
+            frames / CUE_FRAMES_PER_SECOND % 60,
+            frames % CUE_FRAMES_PER_SECOND
+        )
+        .unwrap();
+    }
+
+    fs::write(path, sheet).with_context(|| format!("writing CUE sheet '{}'", path.display()))
+}
+
+struct Wav<W: Write + Seek>(hound::WavWriter<W>);
+
+impl<W: Write + Seek> Wav<W> {
+    fn new(writer: W, sample_rate: u32) -> Result<Self> {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let writer = hound::WavWriter::new(writer, spec).context("writing WAVE header")?;
+
+        Ok(Wav(writer))
+    }
+}
+
+impl<W: Write + Seek> Encoder for Wav<W> {
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        let mut wrt = self.0.get_i16_writer(samples.len() as u32);
+        for &sample in samples {
+            unsafe { wrt.write_sample_unchecked(sample) };
+        }
+        wrt.flush().context("writing WAVE samples")
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.0.finalize().context("finalizing WAVE file")
+    }
+}
+
+/// WAVE stream whose header is written upfront, without knowing its length
+struct WavStream<W: Write>(Pcm<W>);
+
+impl<W: Write> WavStream<W> {
+    fn new(mut writer: W, sample_rate: u32) -> Result<Self> {
+        let mut header = Vec::with_capacity(WAV_HEADER_LEN);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&WAV_STREAM_LEN.to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes()); // fmt chunk length
+        header.extend_from_slice(&1u16.to_le_bytes()); // PCM
+        header.extend_from_slice(&1u16.to_le_bytes()); // channel count
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * 2).to_le_bytes()); // byte rate
+        header.extend_from_slice(&2u16.to_le_bytes()); // block align
+        header.extend_from_slice(&16u16.to_le_bytes()); // bits per sample
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&WAV_STREAM_LEN.to_le_bytes());
+
+        writer.write_all(&header).context("writing WAVE header")?;
+
+        Ok(WavStream(Pcm(writer)))
+    }
+}
+
+impl<W: Write> Encoder for WavStream<W> {
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        self.0.write(samples)
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        Box::new(self.0).finish()
+    }
+}
+
+struct Pcm<W: Write>(W);
+
+impl<W: Write> Encoder for Pcm<W> {
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        for &sample in samples {
+            self.0
+                .write_all(&sample.to_le_bytes())
+                .context("writing PCM samples")?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.0.flush().context("flushing PCM output")
+    }
+}
+
+/// FLAC stream, encoded into a temporary file first
+///
+/// libFLAC only writes to seekable outputs it owns, so the stream is copied
+/// over to the actual writer once it has been finalized.
+struct Flac<W: Write> {
+    encoder: FlacEncoder<'static>,
+    file: NamedTempFile,
+    writer: W,
+}
+
+impl<W: Write> Flac<W> {
+    fn new(writer: W, sample_rate: u32) -> Result<Self> {
+        let file = NamedTempFile::new().context("creating temporary FLAC file")?;
+        let encoder = FlacEncoder::new()
+            .ok_or_else(|| anyhow!("allocating FLAC encoder"))?
+            .channels(1)
+            .bits_per_sample(16)
+            .sample_rate(sample_rate)
+            .compression_level(5)
+            .init_file(&file.path())
+            .map_err(|e| anyhow!("initializing FLAC encoder: {:?}", e))?;
+
+        Ok(Flac {
+            encoder,
+            file,
+            writer,
+        })
+    }
+}
+
+impl<W: Write> Encoder for Flac<W> {
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        let samples: Vec<i32> = samples.iter().map(|&x| i32::from(x)).collect();
+        let encoder = &mut self.encoder;
+        encoder
+            .process_interleaved(&samples, samples.len() as u32)
+            .map_err(|_| anyhow!("encoding FLAC samples: {:?}", encoder.state()))
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        let Flac {
+            encoder,
+            file,
+            mut writer,
+        } = *self;
+        encoder
+            .finish()
+            .map_err(|encoder| anyhow!("finalizing FLAC stream: {:?}", encoder.state()))?;
+
+        let mut file = file.reopen().context("reopening temporary FLAC file")?;
+        io::copy(&mut file, &mut writer).context("copying FLAC stream to output")?;
+        writer.flush().context("flushing FLAC output")
+    }
+}
+
+struct Mp3<W: Write> {
+    encoder: mp3lame_encoder::Encoder,
+    buffer: Vec<u8>,
+    writer: W,
+}
+
+impl<W: Write> Mp3<W> {
+    fn new(writer: W, sample_rate: u32) -> Result<Self> {
+        let mut builder =
+            mp3lame_encoder::Builder::new().ok_or_else(|| anyhow!("allocating MP3 encoder"))?;
+        Self::configure(&mut builder, sample_rate)
+            .map_err(|e| anyhow!("configuring MP3 encoder: {:?}", e))?;
+        let encoder = builder
+            .build()
+            .map_err(|e| anyhow!("initializing MP3 encoder: {:?}", e))?;
+
+        Ok(Mp3 {
+            encoder,
+            buffer: Vec::new(),
+            writer,
+        })
+    }
+
+    fn configure(
+        builder: &mut mp3lame_encoder::Builder,
+        sample_rate: u32,
+    ) -> std::result::Result<(), mp3lame_encoder::BuildError> {
+        builder.set_num_channels(1)?;
+        builder.set_sample_rate(sample_rate)?;
+        builder.set_brate(Bitrate::Kbps64)?;
+        builder.set_quality(Quality::Good)
+    }
+
+    fn write_buffer(&mut self) -> Result<()> {
+        self.writer
+            .write_all(&self.buffer)
+            .context("writing MP3 frames")?;
+        self.buffer.clear();
+
+        Ok(())
+    }
+}
+
+impl<W: Write> Encoder for Mp3<W> {
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        self.encoder
+            .encode_to_vec(MonoPcm(samples), &mut self.buffer)
+            .map_err(|e| anyhow!("encoding MP3 frames: {:?}", e))?;
+        self.write_buffer()
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.encoder
+            .flush_to_vec::<FlushNoGap>(&mut self.buffer)
+            .map_err(|e| anyhow!("flushing MP3 encoder: {:?}", e))?;
+        self.write_buffer()?;
+        self.writer.flush().context("flushing MP3 output")
+    }
+}
+
+/// Opus stream in an Ogg container, as described by RFC 7845
+struct Opus<W: Write> {
+    encoder: OpusEncoder,
+    writer: PacketWriter<W>,
+    sample_rate: u32,
+    /// Samples not yet making up a whole frame
+    pending: Vec<i16>,
+    /// Number of samples encoded so far, at the input sample rate
+    encoded: u64,
+}
+
+impl<W: Write> Opus<W> {
+    fn new(writer: W, sample_rate: u32) -> Result<Self> {
+        let rate = match sample_rate {
+            8000 => SampleRate::Hz8000,
+            12000 => SampleRate::Hz12000,
+            16000 => SampleRate::Hz16000,
+            24000 => SampleRate::Hz24000,
+            48000 => SampleRate::Hz48000,
+            other => bail!("Opus doesn't support sample rate of {} Hz", other),
+        };
+        let encoder = OpusEncoder::new(rate, Channels::Mono, Application::Voip)
+            .context("initializing Opus encoder")?;
+        let mut writer = PacketWriter::new(writer);
+
+        let mut head = b"OpusHead".to_vec();
+        head.push(1); // version
+        head.push(1); // channel count
+        head.extend_from_slice(&OPUS_PRE_SKIP.to_le_bytes());
+        head.extend_from_slice(&sample_rate.to_le_bytes());
+        head.extend_from_slice(&0i16.to_le_bytes()); // output gain
+        head.push(0); // channel mapping family
+
+        let vendor = concat!("g_flite ", env!("CARGO_PKG_VERSION"));
+        let mut tags = b"OpusTags".to_vec();
+        tags.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+        tags.extend_from_slice(vendor.as_bytes());
+        tags.extend_from_slice(&0u32.to_le_bytes()); // user comment count
+
+        for header in [head, tags].iter() {
+            writer
+                .write_packet(
+                    header.clone().into_boxed_slice(),
+                    OGG_SERIAL,
+                    PacketWriteEndInfo::EndPage,
+                    0,
+                )
+                .context("writing Opus headers")?;
+        }
+
+        Ok(Opus {
+            encoder,
+            writer,
+            sample_rate,
+            pending: Vec::new(),
+            encoded: 0,
+        })
+    }
+
+    fn frame_len(&self) -> usize {
+        (self.sample_rate * OPUS_FRAME_MILLIS / 1000) as usize
+    }
+
+    /// Granule position after the samples encoded so far, which Ogg Opus
+    /// always counts at 48kHz
+    fn granule_position(&self) -> u64 {
+        u64::from(OPUS_PRE_SKIP) + self.encoded * 48000 / u64::from(self.sample_rate)
+    }
+
+    /// Encodes a single frame, padding it with silence if it is short; the
+    /// granule position tells the decoder to drop the padding again
+    fn write_frame(&mut self, frame: &[i16], end: PacketWriteEndInfo) -> Result<()> {
+        let mut padded = frame.to_vec();
+        padded.resize(self.frame_len(), 0);
+
+        let mut packet = vec![0; OPUS_MAX_PACKET];
+        let len = self
+            .encoder
+            .encode(&padded, &mut packet)
+            .context("encoding Opus frame")?;
+        packet.truncate(len);
+        self.encoded += frame.len() as u64;
+
+        let granule_position = self.granule_position();
+        self.writer
+            .write_packet(packet.into_boxed_slice(), OGG_SERIAL, end, granule_position)
+            .context("writing Opus packet")
+    }
+}
+
+impl<W: Write> Encoder for Opus<W> {
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        let frame_len = self.frame_len();
+        self.pending.extend_from_slice(samples);
+
+        let num_frames = self.pending.len() / frame_len;
+        let pending = std::mem::take(&mut self.pending);
+        for frame in pending[..num_frames * frame_len].chunks(frame_len) {
+            self.write_frame(frame, PacketWriteEndInfo::NormalPacket)?;
+        }
+        self.pending = pending[num_frames * frame_len..].to_vec();
+
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        let pending = std::mem::take(&mut self.pending);
+        self.write_frame(&pending, PacketWriteEndInfo::EndStream)?;
+
+        self.writer
+            .into_inner()
+            .flush()
+            .context("flushing Opus output")
+    }
+}
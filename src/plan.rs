@@ -0,0 +1,253 @@
+//! Plan of a run, shown instead of computing it: how the input is split
+//! into subtasks and what computing them on Golem would cost.
+
+use super::document::Document;
+use super::input::InputFormat;
+use super::Synthesizer;
+use anyhow::{anyhow, Result};
+use gwasm_api::prelude::Timeout;
+use std::collections::HashSet;
+use std::fmt;
+use std::time::Duration;
+
+/// gWasm has each subtask computed by at least this many providers, so that
+/// their results can be verified against each other
+pub const GWASM_REDUNDANCY: u32 = 2;
+
+/// Works out the length of a gWasm timeout in hours from its HH:MM:SS form
+fn timeout_hours(timeout: &Timeout) -> Result<f64> {
+    let formatted = timeout.to_string();
+    let parts = formatted
+        .split(':')
+        .map(str::parse::<f64>)
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .ok()
+        .filter(|parts| parts.len() == 3)
+        .ok_or_else(|| anyhow!("unexpected format of timeout '{}'", formatted))?;
+
+    Ok(parts[0] + parts[1] / 60.0 + parts[2] / 3600.0)
+}
+
+/// Formats an estimated duration, which is only accurate to seconds anyway
+fn format_estimate(duration: Duration) -> String {
+    humantime::format_duration(Duration::from_secs(duration.as_secs())).to_string()
+}
+
+/// Settings determining what computing a task on Golem costs
+#[derive(Debug, Clone)]
+pub struct Pricing {
+    /// Price per hour of computation, in GNT
+    pub bid: f64,
+    pub budget: Option<f64>,
+    /// Longest a subtask may be computed for, in hours
+    pub subtask_hours: f64,
+}
+
+impl Pricing {
+    pub fn new(bid: f64, budget: Option<f64>, subtask_timeout: &Timeout) -> Result<Self> {
+        Ok(Self {
+            bid,
+            budget,
+            subtask_hours: timeout_hours(subtask_timeout)?,
+        })
+    }
+
+    /// Most that computing `num_subtasks` subtasks may cost, in GNT
+    pub fn cost(&self, num_subtasks: usize) -> f64 {
+        num_subtasks as f64 * f64::from(GWASM_REDUNDANCY) * self.bid * self.subtask_hours
+    }
+}
+
+/// A chunk of a document as it would be computed
+#[derive(Debug, Clone)]
+pub struct ChunkPlan {
+    /// Title of the chapter starting with the chunk, if any
+    pub chapter: Option<String>,
+    pub num_words: usize,
+    pub num_chars: usize,
+    /// Estimated length of the audio, excluding the pause after it
+    pub audio: Duration,
+    pub pause: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct DocumentPlan {
+    /// Description of the document, such as its input file
+    pub name: String,
+    pub chunks: Vec<ChunkPlan>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub documents: Vec<DocumentPlan>,
+    /// Number of distinct chunks, which are computed just once each
+    pub num_unique: usize,
+    /// Number of distinct chunks found in the cache
+    pub num_cached: usize,
+    /// Set when computing on Golem, which is paid for
+    pub pricing: Option<Pricing>,
+}
+
+impl Plan {
+    /// Splits `documents` the way `synth` would synthesize them, looking
+    /// the chunks up in its cache if it has one; `input_format` overrides
+    /// the format inferred from the extension of each input file
+    pub fn new(
+        synth: &Synthesizer,
+        documents: &[Document],
+        input_format: Option<InputFormat>,
+    ) -> Result<Self> {
+        let mut plans = Vec::with_capacity(documents.len());
+        let mut unique = HashSet::new();
+
+        for document in documents {
+            let (chunks, chapters) = document.split(synth, input_format)?;
+            let pauses = synth.pauses(&chunks);
+            let task = synth.task(&chunks);
+
+            let mut chapters = chapters.into_iter().peekable();
+            let chunks = chunks
+                .iter()
+                .zip(pauses)
+                .enumerate()
+                .map(|(i, (chunk, pause))| ChunkPlan {
+                    chapter: chapters
+                        .next_if(|chapter| chapter.start == i)
+                        .map(|chapter| chapter.title),
+                    num_words: chunk.num_words,
+                    num_chars: chunk.text.chars().count(),
+                    audio: synth.estimate_duration(chunk),
+                    pause,
+                })
+                .collect();
+            unique.extend(task.subtasks);
+
+            plans.push(DocumentPlan {
+                name: document.to_string(),
+                chunks,
+            });
+        }
+
+        let num_cached = match synth.cache() {
+            Some(cache) => unique
+                .iter()
+                .filter(|subtask| cache.contains(subtask).unwrap_or(false))
+                .count(),
+            None => 0,
+        };
+
+        Ok(Self {
+            documents: plans,
+            num_unique: unique.len(),
+            num_cached,
+            pricing: None,
+        })
+    }
+
+    /// Sets what computing the plan on Golem costs
+    pub fn pricing(mut self, pricing: Pricing) -> Self {
+        self.pricing = Some(pricing);
+        self
+    }
+
+    fn chunks(&self) -> impl Iterator<Item = &ChunkPlan> {
+        self.documents
+            .iter()
+            .flat_map(|document| document.chunks.iter())
+    }
+
+    pub fn num_chunks(&self) -> usize {
+        self.chunks().count()
+    }
+
+    /// Number of subtasks which actually need computing
+    pub fn num_subtasks(&self) -> usize {
+        self.num_unique - self.num_cached
+    }
+
+    /// Estimated length of all of the audio, pauses included
+    pub fn duration(&self) -> Duration {
+        self.chunks().map(|chunk| chunk.audio + chunk.pause).sum()
+    }
+
+    /// Most that computing the plan may cost, in GNT, if it is paid for
+    pub fn cost(&self) -> Option<f64> {
+        self.pricing
+            .as_ref()
+            .map(|pricing| pricing.cost(self.num_subtasks()))
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for document in &self.documents {
+            writeln!(f, "Chunks of {}:", document.name)?;
+            writeln!(
+                f,
+                "  {:>5}  {:>6}  {:>8}  {:>10}  {:>10}",
+                "chunk", "words", "chars", "audio", "pause"
+            )?;
+            for (i, chunk) in document.chunks.iter().enumerate() {
+                if let Some(chapter) = &chunk.chapter {
+                    writeln!(f, "  {}", chapter)?;
+                }
+                writeln!(
+                    f,
+                    "  {:>5}  {:>6}  {:>8}  {:>10}  {:>10}",
+                    i,
+                    chunk.num_words,
+                    chunk.num_chars,
+                    format_estimate(chunk.audio),
+                    humantime::format_duration(chunk.pause).to_string(),
+                )?;
+            }
+        }
+
+        writeln!(f)?;
+        let documents = if self.documents.len() > 1 {
+            format!(" in {} documents", self.documents.len())
+        } else {
+            String::new()
+        };
+        let num_chunks = self.num_chunks();
+        writeln!(
+            f,
+            "{} chunks{}: {} words, {} characters, about {} of audio",
+            num_chunks,
+            documents,
+            self.chunks().map(|chunk| chunk.num_words).sum::<usize>(),
+            self.chunks().map(|chunk| chunk.num_chars).sum::<usize>(),
+            format_estimate(self.duration()),
+        )?;
+        write!(
+            f,
+            "{} subtasks to compute; {} chunks are duplicates and {} are in the cache",
+            self.num_subtasks(),
+            num_chunks - self.num_unique,
+            self.num_cached,
+        )?;
+
+        if let Some(pricing) = &self.pricing {
+            let cost = pricing.cost(self.num_subtasks());
+            writeln!(f)?;
+            write!(
+                f,
+                "Projected cost: up to {:.4} GNT ({} subtasks x {} providers x {} GNT/h bid x {:.4} h subtask timeout)",
+                cost,
+                self.num_subtasks(),
+                GWASM_REDUNDANCY,
+                pricing.bid,
+                pricing.subtask_hours,
+            )?;
+            match pricing.budget {
+                Some(budget) if budget < cost => {
+                    write!(f, "\nBudget: {} GNT, less than the projected cost", budget)?
+                }
+                Some(budget) => write!(f, "\nBudget: {} GNT", budget)?,
+                None => {}
+            }
+        }
+
+        Ok(())
+    }
+}
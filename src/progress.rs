@@ -0,0 +1,54 @@
+use gwasm_api::prelude::ProgressUpdate;
+use indicatif::ProgressBar;
+use std::cell::Cell;
+
+pub struct ProgressUpdater {
+    bar: ProgressBar,
+    progress: Cell<f64>,
+    num_subtasks: u64,
+}
+
+impl ProgressUpdater {
+    pub fn new(num_subtasks: u64) -> Self {
+        Self {
+            bar: ProgressBar::new(num_subtasks),
+            progress: Cell::new(0.0),
+            num_subtasks,
+        }
+    }
+
+    /// Creates an updater which tracks progress without drawing anything
+    pub fn hidden(num_subtasks: u64) -> Self {
+        Self {
+            bar: ProgressBar::hidden(),
+            progress: Cell::new(0.0),
+            num_subtasks,
+        }
+    }
+
+    /// Stops tracking progress of a cancelled run, leaving the bar where it
+    /// got to, so it's clear how far along the run was
+    pub fn abandon(&self) {
+        self.bar.abandon()
+    }
+}
+
+impl ProgressUpdate for ProgressUpdater {
+    fn update(&self, progress: f64) {
+        let old_progress = self.progress.get();
+        if progress > old_progress {
+            let delta = progress - old_progress;
+            self.progress.set(progress);
+            self.bar
+                .inc((delta * self.num_subtasks as f64).round() as u64);
+        }
+    }
+
+    fn start(&self) {
+        self.bar.inc(0)
+    }
+
+    fn stop(&self) {
+        self.bar.finish_and_clear()
+    }
+}
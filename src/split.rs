@@ -0,0 +1,581 @@
+//! Splitting of input text into chunks that respect the structure of the
+//! text: paragraphs, sentences and clauses.
+//!
+//! The text is first tokenized into words, each word tagged with the kind of
+//! [`Boundary`] that follows it. Chunk boundaries are then placed as close as
+//! possible to the ideal, evenly spaced positions, with each candidate
+//! boundary penalized by how disruptive cutting there would be to the
+//! prosody of the synthesized speech.
+//!
+//! The positions are measured either in words or in an estimate of the time
+//! it takes to speak each word, see [`Balance`].
+
+use super::flite;
+use anyhow::{bail, Result};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Closing punctuation which may trail a sentence terminator, e.g. `."` or `?)`
+pub(crate) const CLOSING: &[char] = &['"', '\'', ')', ']', '}', '”', '’', '»'];
+
+/// Common abbreviations whose trailing period does not end a sentence
+const ABBREVIATIONS: &[&str] = &[
+    "mr", "mrs", "ms", "dr", "prof", "st", "jr", "sr", "vs", "e.g", "i.e", "cf", "no", "fig",
+];
+
+/// Kind of break that follows a word in the input text
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Boundary {
+    Word,
+    Clause,
+    Sentence,
+    Paragraph,
+}
+
+impl Boundary {
+    /// Penalty for cutting a chunk at this boundary, as a fraction of the
+    /// ideal chunk size
+    fn penalty(self) -> f64 {
+        match self {
+            Boundary::Paragraph => 0.0,
+            Boundary::Sentence => 0.05,
+            Boundary::Clause => 0.15,
+            Boundary::Word => 0.4,
+        }
+    }
+
+    /// Length of the pause flite makes at this boundary, in syllables
+    fn pause_syllables(self) -> f64 {
+        match self {
+            Boundary::Paragraph => 2.0,
+            Boundary::Sentence => 1.0,
+            Boundary::Clause => 0.5,
+            Boundary::Word => 0.0,
+        }
+    }
+}
+
+/// Syllables it takes to read out a single digit, e.g. "1984" as "nineteen
+/// eighty four"
+const DIGIT_SYLLABLES: f64 = 1.5;
+/// Syllables it takes to read out a symbol, e.g. "%" as "percent"
+const SYMBOL_SYLLABLES: f64 = 2.0;
+const SYMBOLS: &[char] = &['$', '%', '&', '@', '#', '+', '=', '€', '£'];
+
+/// What the chunks of a split are of equal size in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Balance {
+    /// Number of words
+    #[default]
+    Words,
+    /// Estimated time it takes to speak the words, so that each subtask
+    /// takes about as long to compute; long words and numbers weigh more
+    /// than short ones
+    Duration,
+}
+
+impl FromStr for Balance {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "words" => Ok(Balance::Words),
+            "duration" => Ok(Balance::Duration),
+            other => bail!(
+                "unknown balancing strategy '{}'; expected one of: words, duration",
+                other
+            ),
+        }
+    }
+}
+
+impl Balance {
+    fn weight(self, token: &Token) -> f64 {
+        match self {
+            Balance::Words => 1.0,
+            Balance::Duration => estimate_syllables(token),
+        }
+    }
+}
+
+/// Estimates how many syllables it takes to speak a token, including the
+/// pause after it
+///
+/// Syllables are counted as groups of vowels, which is crude, but accurate
+/// enough to tell long words from short ones.
+pub fn estimate_syllables(token: &Token) -> f64 {
+    let mut syllables = 0.0;
+    let mut prev_vowel = false;
+
+    for c in token.word.chars() {
+        let vowel = "aeiouyAEIOUY".contains(c);
+        if vowel && !prev_vowel {
+            syllables += 1.0;
+        } else if c.is_ascii_digit() {
+            syllables += DIGIT_SYLLABLES;
+        } else if SYMBOLS.contains(&c) {
+            syllables += SYMBOL_SYLLABLES;
+        }
+        prev_vowel = vowel;
+    }
+
+    syllables.max(1.0) + token.boundary.pause_syllables()
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Token<'a> {
+    pub word: &'a str,
+    pub boundary: Boundary,
+}
+
+/// A run of input text to be synthesized with the same flite options
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub text: String,
+    /// flite options overriding the ones set on the command line
+    pub flite: Option<flite::Options>,
+    /// Explicit pause after the segment
+    pub pause: Option<Duration>,
+}
+
+/// A chunk of input text to be synthesized in a single subtask
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    /// Words of the chunk; paragraphs within it are separated by a blank line
+    pub text: String,
+    pub num_words: usize,
+    /// Boundary the chunk ends on
+    pub boundary: Boundary,
+    /// flite options overriding the ones set on the command line
+    pub flite: Option<flite::Options>,
+    /// Explicit pause after the chunk, overriding the one implied by `boundary`
+    pub pause: Option<Duration>,
+}
+
+impl Chunk {
+    fn from_tokens(tokens: &[Token]) -> Self {
+        let mut text = String::new();
+        for (i, token) in tokens.iter().enumerate() {
+            text.push_str(token.word);
+
+            if i + 1 < tokens.len() {
+                text.push_str(match token.boundary {
+                    Boundary::Paragraph => "\n\n",
+                    _ => " ",
+                });
+            }
+        }
+
+        Self {
+            text,
+            num_words: tokens.len(),
+            boundary: tokens
+                .last()
+                .map_or(Boundary::Paragraph, |token| token.boundary),
+            flite: None,
+            pause: None,
+        }
+    }
+
+    pub fn ends_paragraph(&self) -> bool {
+        self.boundary == Boundary::Paragraph
+    }
+}
+
+fn is_abbreviation(word: &str) -> bool {
+    let stem = word.trim_end_matches('.');
+    let mut chars = stem.chars();
+    let is_initial = match (chars.next(), chars.next()) {
+        (Some(c), None) => c.is_uppercase(),
+        _ => false,
+    };
+
+    is_initial || ABBREVIATIONS.contains(&stem.to_lowercase().as_str())
+}
+
+fn classify(word: &str) -> Boundary {
+    let core = word.trim_end_matches(CLOSING);
+
+    match core.chars().last() {
+        Some('.') if is_abbreviation(core) => Boundary::Word,
+        Some('.') | Some('!') | Some('?') | Some('…') => Boundary::Sentence,
+        Some(',') | Some(';') | Some(':') | Some('—') => Boundary::Clause,
+        _ if core == "-" || core == "--" => Boundary::Clause,
+        _ => Boundary::Word,
+    }
+}
+
+/// Splits `text` into whitespace-separated words, tagging each with the
+/// boundary that follows it
+///
+/// A blank line (two or more line breaks) after a word marks the end of a
+/// paragraph, as does the end of the text.
+pub fn tokenize(text: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = text.trim_start();
+
+    while !rest.is_empty() {
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let (word, tail) = rest.split_at(end);
+        let next = tail.trim_start();
+        let gap = &tail[..tail.len() - next.len()];
+
+        let boundary = if next.is_empty() || gap.matches('\n').count() >= 2 {
+            Boundary::Paragraph
+        } else {
+            classify(word)
+        };
+
+        tokens.push(Token { word, boundary });
+        rest = next;
+    }
+
+    tokens
+}
+
+/// Partitions `tokens` into at most `num_chunks` non-empty, contiguous
+/// chunks of roughly equal size, as measured by `balance`, returning the
+/// exclusive end index of each chunk
+pub fn partition(tokens: &[Token], num_chunks: usize, balance: Balance) -> Vec<usize> {
+    let num_tokens = tokens.len();
+    let num_chunks = num_chunks.min(num_tokens);
+
+    if num_chunks == 0 {
+        return Vec::new();
+    }
+
+    // offsets[i] is the size of the first i tokens
+    let mut offsets = Vec::with_capacity(num_tokens + 1);
+    offsets.push(0.0);
+    for token in tokens {
+        offsets.push(offsets[offsets.len() - 1] + balance.weight(token));
+    }
+
+    let chunk_size = offsets[num_tokens] / num_chunks as f64;
+    let score = |end: usize, ideal: f64| {
+        (offsets[end] - ideal).abs() + tokens[end - 1].boundary.penalty() * chunk_size
+    };
+
+    let mut ends = Vec::with_capacity(num_chunks);
+    let mut start = 0;
+
+    for i in 1..num_chunks {
+        let ideal = i as f64 * chunk_size;
+        // leave at least one token for each of the remaining chunks
+        let lo = start + 1;
+        let hi = num_tokens - (num_chunks - i);
+        // no boundary further than one chunk away from the ideal position
+        // can beat a plain word boundary right at it
+        let window_lo = lo
+            .max(
+                offsets
+                    .partition_point(|&offset| offset <= ideal - chunk_size)
+                    .saturating_sub(1),
+            )
+            .min(hi);
+        let window_hi = hi
+            .min(offsets.partition_point(|&offset| offset < ideal + chunk_size))
+            .max(window_lo);
+
+        let end = (window_lo..=window_hi)
+            .min_by(|&a, &b| score(a, ideal).partial_cmp(&score(b, ideal)).unwrap())
+            .unwrap_or(lo);
+
+        ends.push(end);
+        start = end;
+    }
+
+    ends.push(num_tokens);
+    ends
+}
+
+/// Splits `text` into at most `num_chunks` chunks, preferring to cut at
+/// paragraph and sentence boundaries
+pub fn split(text: &str, num_chunks: usize, balance: Balance) -> Vec<Chunk> {
+    let tokens = tokenize(text);
+    let mut start = 0;
+
+    partition(&tokens, num_chunks, balance)
+        .into_iter()
+        .map(|end| {
+            let chunk = Chunk::from_tokens(&tokens[start..end]);
+            start = end;
+            chunk
+        })
+        .collect()
+}
+
+/// Splits `segment` at each blank line into one segment per paragraph, so
+/// that no chunk spans a paragraph boundary and the paragraph pause can be
+/// inserted after each of them
+///
+/// The paragraphs but the last keep the blank line after them, which marks
+/// their end as a paragraph boundary, see [`split_segments`]; the last one
+/// takes over the pause after the segment.
+pub fn split_paragraphs(segment: Segment) -> Vec<Segment> {
+    let mut starts = Vec::new();
+    let mut has_text = false;
+    let mut newlines = 0;
+    for (i, c) in segment.text.char_indices() {
+        if c == '\n' {
+            newlines += 1;
+        } else if !c.is_whitespace() {
+            if has_text && newlines >= 2 {
+                starts.push(i);
+            }
+            has_text = true;
+            newlines = 0;
+        }
+    }
+    if starts.is_empty() {
+        return vec![segment];
+    }
+
+    let mut paragraphs = Vec::with_capacity(starts.len() + 1);
+    let mut start = 0;
+    for end in starts {
+        paragraphs.push(Segment {
+            text: segment.text[start..end].to_string(),
+            flite: segment.flite.clone(),
+            pause: None,
+        });
+        start = end;
+    }
+    paragraphs.push(Segment {
+        text: segment.text[start..].to_string(),
+        flite: segment.flite,
+        pause: segment.pause,
+    });
+
+    paragraphs
+}
+
+/// Joins consecutive segments which share flite options and have no
+/// explicit pause between them, the only segments which need chunks of
+/// their own
+pub fn merge_segments(segments: &[Segment]) -> Vec<Segment> {
+    let mut merged: Vec<Segment> = Vec::new();
+
+    for segment in segments {
+        match merged.last_mut() {
+            Some(last) if last.pause.is_none() && last.flite == segment.flite => {
+                last.text.push_str(&segment.text);
+                last.pause = segment.pause;
+            }
+            _ => merged.push(segment.clone()),
+        }
+    }
+
+    merged
+}
+
+/// Size of each of `segments` as measured by `balance`, along with its
+/// number of words, which is the most chunks it can be split into
+pub fn segment_sizes(segments: &[Segment], balance: Balance) -> Vec<(f64, usize)> {
+    segments
+        .iter()
+        .map(|segment| {
+            let tokens = tokenize(&segment.text);
+            let size = tokens.iter().map(|token| balance.weight(token)).sum();
+            (size, tokens.len())
+        })
+        .collect()
+}
+
+/// Shares `num_chunks` chunks out among parts of the given sizes and
+/// numbers of words, in proportion to their size
+///
+/// Each part with any words gets at least one chunk, and none gets more
+/// chunks than it has words. The counts add up to `num_chunks`, unless
+/// there are more parts with words than that, in which case each of them
+/// gets a single chunk, or fewer words than that.
+pub fn allocate(sizes: &[(f64, usize)], num_chunks: usize) -> Vec<usize> {
+    let total_size: f64 = sizes.iter().map(|&(size, _)| size).sum();
+    let mut counts: Vec<usize> = sizes
+        .iter()
+        .map(|&(_, num_words)| usize::from(num_words > 0))
+        .collect();
+    let mut remaining = num_chunks.saturating_sub(counts.iter().sum());
+
+    while remaining > 0 {
+        // the part furthest behind its proportional share gets the next
+        // chunk, as long as it has words to spare
+        let next = sizes
+            .iter()
+            .zip(&counts)
+            .enumerate()
+            .filter(|(_, (&(_, num_words), &count))| count < num_words)
+            .map(|(i, (&(size, _), &count))| {
+                (i, num_chunks as f64 * size / total_size - count as f64)
+            })
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap());
+
+        match next {
+            Some((i, _)) => counts[i] += 1,
+            None => break,
+        }
+        remaining -= 1;
+    }
+
+    counts
+}
+
+/// Splits `segments` into chunks, each into as many as `counts` says, see
+/// [`allocate`]
+///
+/// Chunks never span two segments, so each segment with any words gets at
+/// least one chunk of its own.
+pub fn split_segments(segments: &[Segment], counts: &[usize], balance: Balance) -> Vec<Chunk> {
+    let mut chunks: Vec<Chunk> = Vec::new();
+
+    for (i, (segment, &count)) in segments.iter().zip(counts).enumerate() {
+        if count == 0 {
+            if let (Some(chunk), Some(pause)) = (chunks.last_mut(), segment.pause) {
+                chunk.pause = Some(chunk.pause.unwrap_or_default() + pause);
+            }
+            continue;
+        }
+
+        let mut segment_chunks = split(&segment.text, count, balance);
+
+        for chunk in &mut segment_chunks {
+            chunk.flite = segment.flite.clone();
+        }
+
+        if let Some(last) = segment_chunks.last_mut() {
+            // the end of a segment is only a paragraph boundary if the text
+            // says so, or if it is the end of the entire input
+            let trailing = &segment.text[segment.text.trim_end().len()..];
+            let ends_paragraph = trailing.matches('\n').count() >= 2;
+            if !ends_paragraph && i + 1 < segments.len() {
+                last.boundary = segment
+                    .text
+                    .split_whitespace()
+                    .last()
+                    .map_or(Boundary::Word, classify);
+            }
+            last.pause = segment.pause;
+        }
+
+        chunks.extend(segment_chunks);
+    }
+
+    chunks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that `ends` make up `num_chunks` non-empty chunks covering
+    /// all of the `num_tokens` tokens
+    fn assert_valid(ends: &[usize], num_tokens: usize, num_chunks: usize) {
+        assert_eq!(ends.len(), num_chunks.min(num_tokens), "ends {:?}", ends);
+        let mut start = 0;
+        for &end in ends {
+            assert!(end > start, "empty or backward chunk in {:?}", ends);
+            start = end;
+        }
+        assert_eq!(start, num_tokens, "ends {:?}", ends);
+    }
+
+    #[test]
+    fn partition_by_duration_with_long_trailing_token() {
+        let text = "one two three four five six 123456789012345678901234567890";
+        let tokens = tokenize(text);
+        let ends = partition(&tokens, 5, Balance::Duration);
+        assert_valid(&ends, tokens.len(), 5);
+
+        let chunks = split(text, 5, Balance::Duration);
+        assert_eq!(chunks.len(), 5);
+        assert_eq!(chunks[4].text, "123456789012345678901234567890");
+    }
+
+    #[test]
+    fn partition_by_duration_never_emits_empty_chunks() {
+        let tokens = tokenize("a b c d 1234567890");
+        let ends = partition(&tokens, 4, Balance::Duration);
+        assert_valid(&ends, tokens.len(), 4);
+    }
+
+    #[test]
+    fn partition_by_duration_with_long_tokens_anywhere() {
+        let long = "12345678901234567890";
+        let texts = [
+            format!("{} a b c d e f g", long),
+            format!("a b {} c d {} e f", long, long),
+            format!("a. b, c {} {} d", long, long),
+            format!("{} {} {}", long, long, long),
+        ];
+
+        for text in texts.iter() {
+            let tokens = tokenize(text);
+            for num_chunks in 1..=tokens.len() + 1 {
+                for &balance in [Balance::Words, Balance::Duration].iter() {
+                    let ends = partition(&tokens, num_chunks, balance);
+                    assert_valid(&ends, tokens.len(), num_chunks);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn partition_prefers_sentence_boundaries() {
+        let tokens = tokenize("One two three. Four five six seven.");
+        assert_eq!(partition(&tokens, 2, Balance::Words), vec![3, 7]);
+    }
+
+    #[test]
+    fn split_keeps_paragraphs() {
+        let chunks = split("One two.\n\nThree four.", 1, Balance::Words);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].text, "One two.\n\nThree four.");
+        assert!(chunks[0].ends_paragraph());
+    }
+
+    #[test]
+    fn split_paragraphs_keeps_the_boundaries() {
+        let segment = Segment {
+            text: "One two.\n\nThree,\nfour.  \n \n Five.\n".into(),
+            flite: None,
+            pause: Some(Duration::from_secs(1)),
+        };
+        let paragraphs = split_paragraphs(segment);
+        let texts: Vec<&str> = paragraphs.iter().map(|p| p.text.as_str()).collect();
+        assert_eq!(texts, ["One two.\n\n", "Three,\nfour.  \n \n ", "Five.\n"]);
+        let pauses: Vec<_> = paragraphs.iter().map(|p| p.pause).collect();
+        assert_eq!(pauses, [None, None, Some(Duration::from_secs(1))]);
+
+        let chunks = split_segments(&paragraphs, &[1, 1, 1], Balance::Words);
+        assert!(chunks.iter().all(Chunk::ends_paragraph));
+    }
+
+    #[test]
+    fn allocate_in_proportion() {
+        let counts = allocate(&[(30.0, 30), (10.0, 10), (20.0, 20)], 6);
+        assert_eq!(counts, vec![3, 1, 2]);
+    }
+
+    #[test]
+    fn allocate_never_exceeds_the_limit() {
+        let sizes: Vec<(f64, usize)> = (1..=20).map(|i| (i as f64, i)).collect();
+        for num_chunks in 20..100 {
+            let counts = allocate(&sizes, num_chunks);
+            assert_eq!(counts.iter().sum::<usize>(), num_chunks.min(210));
+            assert!(counts.iter().all(|&count| count >= 1));
+        }
+    }
+
+    #[test]
+    fn allocate_gives_every_part_a_chunk() {
+        // many tiny parts and one large one, which used to be rounded up
+        // to more chunks than asked for
+        let mut sizes = vec![(1.0, 1); 5];
+        sizes.push((100.0, 100));
+        let counts = allocate(&sizes, 8);
+        assert_eq!(counts, vec![1, 1, 1, 1, 1, 3]);
+
+        // more parts than chunks
+        assert_eq!(allocate(&[(1.0, 1), (0.0, 0), (2.0, 2)], 1), vec![1, 0, 1]);
+    }
+}
@@ -0,0 +1,374 @@
+//! Reading of SSML input.
+//!
+//! The document is flattened into [`Segment`]s of text which share the same
+//! flite options. `<prosody>` and `<voice>` map onto the options of the
+//! enclosed text, `<break>` onto a pause after the preceding segment, and
+//! `<say-as>` onto a rewrite of the enclosed text. Other elements, such as
+//! `<speak>`, `<p>` or `<s>`, only contribute their text.
+
+use super::flite;
+use super::split::Segment;
+use anyhow::{anyhow, bail, Result};
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::time::Duration;
+
+#[derive(Debug, Clone)]
+struct State {
+    flite: flite::Options,
+    interpret_as: Option<String>,
+}
+
+fn attribute(element: &BytesStart, name: &[u8]) -> Result<Option<String>> {
+    for attribute in element.attributes() {
+        let attribute = attribute.map_err(|e| anyhow!("parsing SSML attribute: {}", e))?;
+        if attribute.key == name {
+            let value = attribute
+                .unescaped_value()
+                .map_err(|e| anyhow!("unescaping SSML attribute: {}", e))?;
+            return Ok(Some(String::from_utf8_lossy(&value).into_owned()));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Parses a relative value such as `80%`, `+10%`, `-3dB`, `+2st` or `1.5`
+/// into a factor
+fn parse_relative(value: &str) -> Option<f64> {
+    let value = value.trim();
+    let (number, unit) = value
+        .find(|c: char| c.is_alphabetic() || c == '%')
+        .map_or((value, ""), |i| value.split_at(i));
+    let signed = number.starts_with('+') || number.starts_with('-');
+    let number: f64 = number.parse().ok()?;
+
+    match unit {
+        "%" if signed => Some(1.0 + number / 100.0),
+        "%" => Some(number / 100.0),
+        "dB" => Some(10f64.powf(number / 20.0)),
+        "st" => Some(2f64.powf(number / 12.0)),
+        "" if !signed => Some(number),
+        _ => None,
+    }
+}
+
+fn parse_prosody(value: &str, keywords: &[(&str, f64)], attribute: &str) -> Result<f64> {
+    keywords
+        .iter()
+        .find(|(keyword, _)| *keyword == value)
+        .map(|(_, factor)| *factor)
+        .or_else(|| parse_relative(value))
+        .ok_or_else(|| anyhow!("unsupported SSML prosody {} '{}'", attribute, value))
+}
+
+fn apply_prosody(element: &BytesStart, options: &mut flite::Options) -> Result<()> {
+    if let Some(rate) = attribute(element, b"rate")? {
+        let keywords = [
+            ("x-slow", 0.5),
+            ("slow", 0.75),
+            ("medium", 1.0),
+            ("fast", 1.25),
+            ("x-fast", 1.5),
+            ("default", 1.0),
+        ];
+        options.rate = (options.rate * parse_prosody(&rate, &keywords, "rate")?).clamp(0.25, 4.0);
+    }
+
+    if let Some(pitch) = attribute(element, b"pitch")? {
+        let keywords = [
+            ("x-low", 0.7),
+            ("low", 0.85),
+            ("medium", 1.0),
+            ("high", 1.15),
+            ("x-high", 1.3),
+            ("default", 1.0),
+        ];
+        options.pitch =
+            (options.pitch * parse_prosody(&pitch, &keywords, "pitch")?).clamp(0.5, 2.0);
+    }
+
+    if let Some(volume) = attribute(element, b"volume")? {
+        let keywords = [
+            ("silent", 0.0),
+            ("x-soft", 0.25),
+            ("soft", 0.5),
+            ("medium", 1.0),
+            ("loud", 1.5),
+            ("x-loud", 2.0),
+            ("default", 1.0),
+        ];
+        options.volume =
+            (options.volume * parse_prosody(&volume, &keywords, "volume")?).clamp(0.0, 4.0);
+    }
+
+    Ok(())
+}
+
+/// Parses an SSML time designation, a number of seconds or milliseconds
+/// such as `1.5s` or `250ms`
+fn parse_time(time: &str) -> Option<Duration> {
+    let time = time.trim();
+    let (number, unit_secs) = match time.strip_suffix("ms") {
+        Some(number) => (number, 0.001),
+        None => (time.strip_suffix('s')?, 1.0),
+    };
+    let number: f64 = number.parse().ok()?;
+
+    // rejects negative, infinite and NaN times as well as those too long
+    // to be represented
+    Duration::try_from_secs_f64(number * unit_secs).ok()
+}
+
+fn parse_break(element: &BytesStart) -> Result<Duration> {
+    if let Some(time) = attribute(element, b"time")? {
+        return parse_time(&time).ok_or_else(|| {
+            anyhow!(
+                "unsupported SSML break time '{}'; expected e.g. 500ms or 1.5s",
+                time
+            )
+        });
+    }
+
+    let millis = match attribute(element, b"strength")?.as_deref() {
+        Some("none") => 0,
+        Some("x-weak") => 100,
+        Some("weak") => 200,
+        Some("medium") | None => 400,
+        Some("strong") => 700,
+        Some("x-strong") => 1000,
+        Some(other) => bail!("unsupported SSML break strength '{}'", other),
+    };
+
+    Ok(Duration::from_millis(millis))
+}
+
+/// Rewrites text according to the `interpret-as` attribute of `<say-as>`
+fn say_as(interpret_as: &str, text: &str) -> String {
+    let spell = |keep: fn(&char) -> bool| {
+        text.chars()
+            .filter(keep)
+            .map(String::from)
+            .collect::<Vec<_>>()
+            .join(" ")
+    };
+
+    match interpret_as {
+        "characters" | "spell-out" | "letters" => spell(|c| !c.is_whitespace()),
+        "digits" | "telephone" => spell(|c| c.is_ascii_digit()),
+        _ => text.to_string(),
+    }
+}
+
+struct Parser {
+    segments: Vec<Segment>,
+    text: String,
+    flite: flite::Options,
+}
+
+impl Parser {
+    fn flush(&mut self) {
+        if !self.text.trim().is_empty() {
+            self.segments.push(Segment {
+                text: std::mem::take(&mut self.text),
+                flite: Some(self.flite.clone()),
+                pause: None,
+            });
+        }
+        self.text.clear();
+    }
+
+    fn push_text(&mut self, text: &str, state: &State) {
+        if state.flite != self.flite {
+            self.flush();
+            self.flite = state.flite.clone();
+        }
+
+        match &state.interpret_as {
+            Some(interpret_as) => self.text.push_str(&say_as(interpret_as, text)),
+            None => self.text.push_str(text),
+        }
+    }
+
+    fn push_break(&mut self, pause: Duration) {
+        self.flush();
+
+        // a break before any text has nothing to follow
+        if let Some(segment) = self.segments.last_mut() {
+            segment.pause = Some(segment.pause.unwrap_or_default() + pause);
+        }
+    }
+}
+
+/// Parses an SSML document into segments, using `base` as the flite options
+/// of the top-level text
+pub fn parse(contents: &str, base: &flite::Options) -> Result<Vec<Segment>> {
+    let mut reader = Reader::from_str(contents);
+    reader.trim_text(false).check_end_names(true);
+
+    let mut stack = vec![State {
+        flite: base.clone(),
+        interpret_as: None,
+    }];
+    let mut parser = Parser {
+        segments: Vec::new(),
+        text: String::new(),
+        flite: base.clone(),
+    };
+    let mut buf = Vec::new();
+
+    loop {
+        let event = reader.read_event(&mut buf).map_err(|e| {
+            anyhow!(
+                "parsing SSML at position {}: {}",
+                reader.buffer_position(),
+                e
+            )
+        })?;
+
+        match event {
+            Event::Start(element) => {
+                let mut state = stack.last().cloned().unwrap();
+
+                match element.local_name() {
+                    b"prosody" => apply_prosody(&element, &mut state.flite)?,
+                    b"voice" => {
+                        if let Some(name) = attribute(&element, b"name")? {
+                            if !flite::is_voice(&name) {
+                                bail!(
+                                    "SSML voice '{}' is not available in the embedded flite build",
+                                    name
+                                );
+                            }
+                            state.flite.voice = Some(name);
+                        }
+                    }
+                    b"say-as" => state.interpret_as = attribute(&element, b"interpret-as")?,
+                    // `<break time="1s"></break>` is as valid as `<break/>`
+                    b"break" => parser.push_break(parse_break(&element)?),
+                    _ => {}
+                }
+
+                stack.push(state);
+            }
+            Event::End(element) => {
+                stack.pop();
+
+                if element.local_name() == b"p" {
+                    parser.text.push_str("\n\n");
+                }
+            }
+            Event::Empty(element) if element.local_name() == b"break" => {
+                parser.push_break(parse_break(&element)?);
+            }
+            Event::Text(text) => {
+                let text = text
+                    .unescape_and_decode(&reader)
+                    .map_err(|e| anyhow!("decoding SSML text: {}", e))?;
+                parser.push_text(&text, stack.last().unwrap());
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+
+        buf.clear();
+    }
+
+    parser.flush();
+
+    Ok(parser.segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pauses(contents: &str) -> Vec<(String, Option<Duration>)> {
+        parse(contents, &flite::Options::default())
+            .unwrap()
+            .into_iter()
+            .map(|segment| (segment.text.trim().to_string(), segment.pause))
+            .collect()
+    }
+
+    #[test]
+    fn empty_break() {
+        assert_eq!(
+            pauses(r#"<speak>Hello <break time="1s"/> world</speak>"#),
+            vec![
+                ("Hello".to_string(), Some(Duration::from_secs(1))),
+                ("world".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn break_with_end_tag() {
+        assert_eq!(
+            pauses(r#"<speak>Hello <break time="1s"></break> world</speak>"#),
+            vec![
+                ("Hello".to_string(), Some(Duration::from_secs(1))),
+                ("world".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn break_strength_and_default() {
+        assert_eq!(
+            pauses(r#"<speak>A <break strength="strong"/> B <break/> C</speak>"#),
+            vec![
+                ("A".to_string(), Some(Duration::from_millis(700))),
+                ("B".to_string(), Some(Duration::from_millis(400))),
+                ("C".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn consecutive_breaks_add_up() {
+        assert_eq!(
+            pauses(r#"<speak>A <break time="200ms"/><break time="0.3s"/> B</speak>"#),
+            vec![
+                ("A".to_string(), Some(Duration::from_millis(500))),
+                ("B".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn break_times() {
+        assert_eq!(parse_time("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_time(" 250ms "), Some(Duration::from_millis(250)));
+        assert_eq!(parse_time("0.5ms"), Some(Duration::from_micros(500)));
+        assert_eq!(parse_time("2"), None);
+        assert_eq!(parse_time("1m"), None);
+        assert_eq!(parse_time("-1s"), None);
+        assert_eq!(parse_time("1e30s"), None);
+        assert_eq!(parse_time("infs"), None);
+        assert!(parse(
+            r#"<speak>A <break time="soon"/> B</speak>"#,
+            &flite::Options::default()
+        )
+        .is_err());
+        assert!(parse(
+            r#"<speak>A <break time="1e30s"/> B</speak>"#,
+            &flite::Options::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn prosody_and_say_as() {
+        let segments = parse(
+            r#"<speak>Call <say-as interpret-as="digits">12</say-as>. <prosody rate="slow">Slowly.</prosody></speak>"#,
+            &flite::Options::default(),
+        )
+        .unwrap();
+
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].text.trim(), "Call 1 2.");
+        assert_eq!(segments[1].text, "Slowly.");
+        assert_eq!(segments[1].flite.as_ref().unwrap().rate, 0.75);
+    }
+}
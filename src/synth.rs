@@ -0,0 +1,1162 @@
+//! High-level API turning text into speech.
+
+use super::audio::{self, SeamOptions};
+use super::backend::{Backend, Subtask, Task};
+use super::cache::Cache;
+use super::cancel::CancelToken;
+use super::flite;
+use super::input::{self, InputFormat};
+use super::lexicon::Lexicon;
+use super::normalize::Normalizer;
+use super::output::{self, Encoder, OutputFormat};
+use super::progress::ProgressUpdater;
+use super::split::{self, Balance, Chunk, Segment};
+use anyhow::{anyhow, bail, Context, Result};
+use std::collections::HashMap;
+use std::io::{Cursor, Read, Seek, Write};
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+/// Synthesized speech as mono 16-bit samples
+#[derive(Debug, Clone, Default)]
+pub struct Audio {
+    pub sample_rate: u32,
+    pub samples: Vec<i16>,
+}
+
+/// Encoder collecting the samples in memory
+struct Collect<'a>(&'a mut Vec<i16>);
+
+impl Encoder for Collect<'_> {
+    fn write(&mut self, samples: &[i16]) -> Result<()> {
+        self.0.extend_from_slice(samples);
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn check_range(name: &str, value: f64, range: RangeInclusive<f64>) -> Result<f64> {
+    if !range.contains(&value) {
+        bail!(
+            "Invalid {} {}; expected a value between {} and {}",
+            name,
+            value,
+            range.start(),
+            range.end()
+        );
+    }
+
+    Ok(value)
+}
+
+/// Checks that the output of a subtask is a WAVE file at all, so that
+/// garbage returned by a misbehaving provider is retried like a failure
+fn check_output(output: &[u8]) -> Result<()> {
+    hound::WavReader::new(Cursor::new(output)).context("parsing subtask output as WAVE")?;
+
+    Ok(())
+}
+
+/// Checks the output of a subtask with [`check_output`] and scales it to
+/// `volume`, in percent, if given
+fn apply_volume(output: Vec<u8>, volume: Option<u16>) -> Result<Vec<u8>> {
+    let volume = match volume {
+        Some(volume) => volume,
+        None => return check_output(&output).map(|_| output),
+    };
+
+    let reader =
+        hound::WavReader::new(Cursor::new(&output)).context("parsing subtask output as WAVE")?;
+    let spec = reader.spec();
+    if spec.bits_per_sample != 16 {
+        bail!(
+            "expected 16-bit audio from subtask, got {}-bit",
+            spec.bits_per_sample
+        );
+    }
+    let mut samples = reader
+        .into_samples::<i16>()
+        .collect::<hound::Result<Vec<_>>>()
+        .context("reading subtask output samples")?;
+    audio::amplify(&mut samples, f64::from(volume) / 100.0);
+
+    let mut scaled = Vec::new();
+    let mut writer = hound::WavWriter::new(Cursor::new(&mut scaled), spec)
+        .context("creating scaled subtask output")?;
+    for sample in samples {
+        writer
+            .write_sample(sample)
+            .context("writing scaled audio sample")?;
+    }
+    writer
+        .finalize()
+        .context("finalizing scaled subtask output")?;
+
+    Ok(scaled)
+}
+
+/// Audio written by [`combine`]
+#[derive(Debug, Clone)]
+pub struct Combined {
+    pub sample_rate: u32,
+    /// Offset of the start of each chunk within the audio, in samples
+    pub offsets: Vec<u64>,
+}
+
+/// Stitches the WAVE outputs of consecutive chunks back together
+///
+/// The encoder is created with `create_encoder` once the sample rate of the
+/// first output is known; that rate is also returned along with where each
+/// chunk starts, unless there were no outputs at all.
+pub fn combine<'w, R: Read>(
+    outputs: impl IntoIterator<Item = R>,
+    pauses: &[Duration],
+    seams: &SeamOptions,
+    create_encoder: impl FnOnce(u32) -> Result<Box<dyn Encoder + 'w>>,
+) -> Result<Option<Combined>> {
+    let mut create_encoder = Some(create_encoder);
+    let mut encoder: Option<(Box<dyn Encoder + 'w>, u32)> = None;
+    let mut prev: Option<Vec<i16>> = None;
+    let mut offsets = Vec::new();
+    let mut written = 0;
+
+    for (i, reader) in outputs.into_iter().enumerate() {
+        let reader = hound::WavReader::new(reader).context("parsing WAVE input")?;
+        let spec = reader.spec();
+
+        if spec.channels != 1 || spec.bits_per_sample != 16 {
+            bail!(
+                "expected 16-bit mono audio from subtask '{}', got {}-bit with {} channels",
+                i,
+                spec.bits_per_sample,
+                spec.channels
+            );
+        }
+
+        if let Some(create_encoder) = create_encoder.take() {
+            encoder = Some((create_encoder(spec.sample_rate)?, spec.sample_rate));
+        }
+        // voices differ in sample rate, so chunks synthesized with different
+        // voices are resampled to the rate of the first one
+        let (encoder, sample_rate) = encoder.as_mut().unwrap();
+        let sample_rate = *sample_rate;
+
+        let mut samples = reader
+            .into_samples::<i16>()
+            .collect::<hound::Result<Vec<_>>>()
+            .with_context(|| format!("reading audio samples from subtask '{}'", i))?;
+
+        if spec.sample_rate != sample_rate {
+            samples = audio::resample(&samples, spec.sample_rate, sample_rate);
+        }
+
+        if seams.trim_silence {
+            audio::trim_silence(&mut samples, sample_rate);
+        }
+
+        if let Some(mut prev) = prev.take() {
+            let pause = pauses.get(i - 1).cloned().unwrap_or_default();
+            audio::join(seams, &mut prev, &mut samples, pause, sample_rate);
+
+            let silence = vec![0; audio::num_samples(pause, sample_rate)];
+            encoder
+                .write(&prev)
+                .and_then(|_| encoder.write(&silence))
+                .context("writing audio samples")?;
+            written += (prev.len() + silence.len()) as u64;
+        }
+
+        offsets.push(written);
+        prev = Some(samples);
+    }
+
+    let (mut encoder, sample_rate) = match encoder {
+        Some(encoder) => encoder,
+        None => return Ok(None),
+    };
+    if let Some(prev) = prev {
+        encoder.write(&prev).context("writing audio samples")?;
+    }
+    encoder.finish().context("finishing output")?;
+
+    Ok(Some(Combined {
+        sample_rate,
+        offsets,
+    }))
+}
+
+/// How the number of chunks the input is split into is worked out
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chunking {
+    /// Fixed number of chunks
+    Subtasks(usize),
+    /// As many chunks as needed for each to have about this many words
+    Words(usize),
+    /// As many chunks as needed for each to take about this long to speak,
+    /// going by [`flite::WORDS_PER_MINUTE`]
+    Duration(Duration),
+}
+
+impl Default for Chunking {
+    fn default() -> Self {
+        Chunking::Subtasks(6)
+    }
+}
+
+/// Builder for a [`Synthesizer`]
+#[derive(Debug)]
+pub struct SynthesizerBuilder {
+    backend: Option<Box<dyn Backend>>,
+    cache: Option<Cache>,
+    input_format: InputFormat,
+    code_phrase: Option<String>,
+    normalizer: Normalizer,
+    lexicon: Lexicon,
+    chunking: Chunking,
+    max_subtasks: Option<usize>,
+    balance: Balance,
+    flite: flite::Options,
+    paragraph_pause: Duration,
+    chunk_pause: Duration,
+    seams: SeamOptions,
+    max_retries: u32,
+    show_progress: bool,
+    cancel: CancelToken,
+}
+
+impl Default for SynthesizerBuilder {
+    fn default() -> Self {
+        Self {
+            backend: None,
+            cache: None,
+            input_format: InputFormat::Text,
+            code_phrase: None,
+            normalizer: Normalizer::default(),
+            lexicon: Lexicon::default(),
+            chunking: Chunking::default(),
+            max_subtasks: None,
+            balance: Balance::default(),
+            flite: flite::Options::default(),
+            paragraph_pause: Duration::from_millis(600),
+            chunk_pause: Duration::from_millis(0),
+            seams: SeamOptions::default(),
+            max_retries: 2,
+            show_progress: false,
+            cancel: CancelToken::new(),
+        }
+    }
+}
+
+impl SynthesizerBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the backend the subtasks are computed on; a synthesizer without
+    /// one can still split and normalize text, but not synthesize it
+    pub fn backend(mut self, backend: Box<dyn Backend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Takes the subtasks synthesized before from `cache`, rather than
+    /// computing them again, and stores the ones computed anew there; by
+    /// default, nothing is cached
+    pub fn cache(mut self, cache: Cache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    pub fn input_format(mut self, input_format: InputFormat) -> Self {
+        self.input_format = input_format;
+        self
+    }
+
+    /// Replaces code blocks in Markdown and HTML input with `phrase`,
+    /// rather than skipping them
+    pub fn code_phrase<S: Into<String>>(mut self, phrase: S) -> Self {
+        self.code_phrase = Some(phrase.into());
+        self
+    }
+
+    /// Sets the normalizer rewriting the text before it is split; by
+    /// default, the text is left as it is
+    pub fn normalizer(mut self, normalizer: Normalizer) -> Self {
+        self.normalizer = normalizer;
+        self
+    }
+
+    /// Sets the lexicon of custom pronunciations the chunks are synthesized
+    /// with; by default, flite's own lexicon is used alone
+    pub fn lexicon(mut self, lexicon: Lexicon) -> Self {
+        self.lexicon = lexicon;
+        self
+    }
+
+    /// Splits the input into a fixed number of chunks
+    pub fn subtasks(mut self, num_subtasks: usize) -> Self {
+        self.chunking = Chunking::Subtasks(num_subtasks);
+        self
+    }
+
+    /// Splits the input into chunks of about `num_words` words each
+    pub fn chunk_words(mut self, num_words: usize) -> Self {
+        self.chunking = Chunking::Words(num_words);
+        self
+    }
+
+    /// Splits the input into chunks taking about `duration` to speak each
+    pub fn chunk_duration(mut self, duration: Duration) -> Self {
+        self.chunking = Chunking::Duration(duration);
+        self
+    }
+
+    pub fn chunking(mut self, chunking: Chunking) -> Self {
+        self.chunking = chunking;
+        self
+    }
+
+    /// Caps the number of chunks the input is split into
+    pub fn max_subtasks(mut self, max_subtasks: usize) -> Self {
+        self.max_subtasks = Some(max_subtasks);
+        self
+    }
+
+    /// Sets what the chunks are of equal size in
+    pub fn balance(mut self, balance: Balance) -> Self {
+        self.balance = balance;
+        self
+    }
+
+    /// Sets flite voice; see [`flite::VOICES`] for the available ones
+    pub fn voice<S: Into<String>>(mut self, voice: S) -> Self {
+        self.flite.voice = Some(voice.into());
+        self
+    }
+
+    /// Sets speaking rate relative to normal, from 0.25 to 4.0
+    pub fn rate(mut self, rate: f64) -> Self {
+        self.flite.rate = rate;
+        self
+    }
+
+    /// Sets pitch relative to the voice's default, from 0.5 to 2.0
+    pub fn pitch(mut self, pitch: f64) -> Self {
+        self.flite.pitch = pitch;
+        self
+    }
+
+    /// Sets volume relative to the voice's default, from 0.0 to 4.0
+    pub fn volume(mut self, volume: f64) -> Self {
+        self.flite.volume = volume;
+        self
+    }
+
+    /// Sets length of silence inserted after each paragraph
+    pub fn paragraph_pause(mut self, pause: Duration) -> Self {
+        self.paragraph_pause = pause;
+        self
+    }
+
+    /// Sets length of silence inserted between chunks within a paragraph
+    pub fn chunk_pause(mut self, pause: Duration) -> Self {
+        self.chunk_pause = pause;
+        self
+    }
+
+    pub fn seams(mut self, seams: SeamOptions) -> Self {
+        self.seams = seams;
+        self
+    }
+
+    /// Sets how many times failed subtasks are resubmitted before giving up
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Draws a progress bar on the terminal while the subtasks are computed
+    pub fn show_progress(mut self, show_progress: bool) -> Self {
+        self.show_progress = show_progress;
+        self
+    }
+
+    /// Lets the computation of the subtasks be cancelled with `token`; see
+    /// [`crate::cancel`]
+    pub fn cancel_token(mut self, token: CancelToken) -> Self {
+        self.cancel = token;
+        self
+    }
+
+    pub fn build(self) -> Result<Synthesizer> {
+        if let Some(voice) = &self.flite.voice {
+            if !flite::is_voice(voice) {
+                bail!("Unknown voice '{}'", voice);
+            }
+        }
+        check_range("rate", self.flite.rate, 0.25..=4.0)?;
+        check_range("pitch", self.flite.pitch, 0.5..=2.0)?;
+        check_range("volume", self.flite.volume, 0.0..=4.0)?;
+
+        match self.chunking {
+            Chunking::Subtasks(0) => bail!("Invalid number of subtasks 0; expected at least 1"),
+            Chunking::Words(0) => bail!("Invalid number of words per chunk 0; expected at least 1"),
+            Chunking::Duration(duration) if duration.as_secs_f64() < 1.0 => bail!(
+                "Invalid length of chunks {}; expected at least 1s",
+                humantime::format_duration(duration)
+            ),
+            _ => {}
+        }
+        if self.max_subtasks == Some(0) {
+            bail!("Invalid maximum number of subtasks 0; expected at least 1");
+        }
+
+        Ok(Synthesizer {
+            backend: self.backend,
+            cache: self.cache,
+            input_format: self.input_format,
+            code_phrase: self.code_phrase,
+            normalizer: self.normalizer,
+            lexicon: self.lexicon,
+            chunking: self.chunking,
+            max_subtasks: self.max_subtasks,
+            balance: self.balance,
+            flite: self.flite,
+            paragraph_pause: self.paragraph_pause,
+            chunk_pause: self.chunk_pause,
+            seams: self.seams,
+            max_retries: self.max_retries,
+            show_progress: self.show_progress,
+            cancel: self.cancel,
+        })
+    }
+}
+
+/// Turns text into speech by splitting it into chunks, synthesizing them on
+/// a [`Backend`] and stitching the results back together
+///
+/// ```no_run
+/// use g_flite::backend::Local;
+/// use g_flite::Synthesizer;
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let synthesizer = Synthesizer::builder()
+///     .backend(Box::new(Local {
+///         workspace: std::env::temp_dir(),
+///     }))
+///     .subtasks(2)
+///     .voice("slt")
+///     .build()?;
+/// let audio = synthesizer.synthesize("Hello there. How are you doing today?")?;
+/// println!("{} samples at {} Hz", audio.samples.len(), audio.sample_rate);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Synthesizer {
+    backend: Option<Box<dyn Backend>>,
+    cache: Option<Cache>,
+    input_format: InputFormat,
+    code_phrase: Option<String>,
+    normalizer: Normalizer,
+    lexicon: Lexicon,
+    chunking: Chunking,
+    max_subtasks: Option<usize>,
+    balance: Balance,
+    flite: flite::Options,
+    paragraph_pause: Duration,
+    chunk_pause: Duration,
+    seams: SeamOptions,
+    max_retries: u32,
+    show_progress: bool,
+    cancel: CancelToken,
+}
+
+impl Synthesizer {
+    pub fn builder() -> SynthesizerBuilder {
+        SynthesizerBuilder::new()
+    }
+
+    pub fn backend(&self) -> Option<&dyn Backend> {
+        self.backend.as_deref()
+    }
+
+    /// Sets the backend the subtasks are computed on, for a synthesizer
+    /// which was built without one or to replace it
+    pub fn set_backend(&mut self, backend: Box<dyn Backend>) {
+        self.backend = Some(backend);
+    }
+
+    pub fn cache(&self) -> Option<&Cache> {
+        self.cache.as_ref()
+    }
+
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    pub fn seams(&self) -> &SeamOptions {
+        &self.seams
+    }
+
+    /// Splits `text` into chunks, as many as the configured [`Chunking`]
+    /// asks for
+    pub fn split(&self, text: &str) -> Result<Vec<Chunk>> {
+        self.split_as(text, self.input_format)
+    }
+
+    /// Same as [`Synthesizer::split`], but reading `text` in the given format
+    /// rather than the configured one
+    pub fn split_as(&self, text: &str, format: InputFormat) -> Result<Vec<Chunk>> {
+        let mut parts = self.split_parts(&[text], format)?;
+        Ok(parts.remove(0))
+    }
+
+    /// Reads `text` in the given format into segments of normalized text
+    fn read(&self, text: &str, format: InputFormat) -> Result<Vec<Segment>> {
+        let mut segments = input::read(format, text, &self.flite, self.code_phrase.as_deref())
+            .context("parsing input")?;
+        if !self.normalizer.is_empty() {
+            for segment in &mut segments {
+                segment.text = self.normalizer.normalize(&segment.text);
+            }
+        }
+
+        Ok(segments)
+    }
+
+    /// Reads `text` in the given format and normalizes it, returning the
+    /// text as it would be synthesized, with segments which differ in flite
+    /// options or are followed by a break separated by a blank line
+    pub fn normalize_as(&self, text: &str, format: InputFormat) -> Result<String> {
+        Ok(self
+            .read(text, format)?
+            .iter()
+            .map(|segment| segment.text.trim())
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n\n"))
+    }
+
+    /// Same as [`Synthesizer::split_as`], but for a text made of several
+    /// parts, such as the chapters of a book, each of which is split into
+    /// chunks of its own
+    ///
+    /// The configured [`Chunking`] applies to the text as a whole, with the
+    /// chunks shared out among the parts, and the runs of text within them
+    /// which differ in flite options or are followed by a break, by their
+    /// size. Since each of those needs at least a chunk of its own, having
+    /// more of them than the number of subtasks allowed is an error.
+    ///
+    /// Paragraphs, and the headings of Markdown and HTML documents, get at
+    /// least a chunk of their own too, so that the paragraph pause can be
+    /// inserted after each of them, even if that makes for more chunks than
+    /// [`Chunking`] asks for; only where that would exceed the cap set with
+    /// [`SynthesizerBuilder::max_subtasks`] do they share chunks.
+    pub fn split_parts(&self, parts: &[&str], format: InputFormat) -> Result<Vec<Vec<Chunk>>> {
+        let parts = parts
+            .iter()
+            .map(|text| self.read(text, format))
+            .collect::<Result<Vec<_>>>()?;
+        let word_counts: Vec<usize> = parts
+            .iter()
+            .map(|segments| {
+                segments
+                    .iter()
+                    .map(|segment| segment.text.split_whitespace().count())
+                    .sum()
+            })
+            .collect();
+        let word_count: usize = word_counts.iter().sum();
+
+        if word_count == 0 {
+            bail!("splitting input into Golem subtasks: input has no words to synthesize");
+        }
+
+        log::info!("Input text has {} words", word_count);
+
+        // only runs of text which differ in flite options or are followed by
+        // a break need chunks of their own
+        let merged: Vec<Vec<Segment>> = parts
+            .iter()
+            .map(|segments| split::merge_segments(segments))
+            .collect();
+        let num_separate = Self::num_separate(&merged);
+        if let Some(limit) = self.subtask_limit() {
+            if num_separate > limit {
+                bail!(
+                    "splitting input into Golem subtasks: input has {} parts which need a subtask of their own, such as chapters, SSML breaks or changes of voice or prosody, but at most {} subtasks are allowed",
+                    num_separate,
+                    limit
+                );
+            }
+        }
+
+        // each paragraph gets chunks of its own, so that the paragraph pause
+        // follows every one of them; if that takes more subtasks than the cap
+        // allows, only the segments as read, such as headings, do, and if
+        // even those are too many, only the runs which need to
+        let paragraphs: Vec<Vec<Segment>> = parts
+            .iter()
+            .map(|segments| {
+                segments
+                    .iter()
+                    .cloned()
+                    .flat_map(split::split_paragraphs)
+                    .collect()
+            })
+            .collect();
+        let num_paragraphs = Self::num_separate(&paragraphs);
+        let parts = match self.max_subtasks {
+            Some(max_subtasks) if num_paragraphs > max_subtasks => {
+                log::warn!(
+                    "Input has {} paragraphs, more than the {} subtasks allowed; the paragraph pause only follows those which end a chunk",
+                    num_paragraphs,
+                    max_subtasks
+                );
+                if Self::num_separate(&parts) > max_subtasks {
+                    merged
+                } else {
+                    parts
+                }
+            }
+            _ => paragraphs,
+        };
+
+        // the chunks are shared out among the segments of all parts at once,
+        // so that their total stays within the limit
+        let num_chunks = self.num_chunks(&parts.concat(), word_count);
+        let sizes: Vec<Vec<(f64, usize)>> = parts
+            .iter()
+            .map(|segments| split::segment_sizes(segments, self.balance))
+            .collect();
+
+        let mut counts = split::allocate(&sizes.concat(), num_chunks).into_iter();
+        let chunks: Vec<Vec<Chunk>> = parts
+            .iter()
+            .map(|segments| {
+                let counts: Vec<usize> = counts.by_ref().take(segments.len()).collect();
+                split::split_segments(segments, &counts, self.balance)
+            })
+            .collect();
+
+        if log::log_enabled!(log::Level::Info) {
+            for (i, chunk) in chunks.iter().flatten().enumerate() {
+                log::info!(
+                    "Chunk {} has {} words and ends on a {:?} boundary",
+                    i,
+                    chunk.num_words,
+                    chunk.boundary,
+                );
+            }
+        }
+
+        Ok(chunks)
+    }
+
+    /// Number of segments with any words in `parts`, each of which needs a
+    /// chunk of its own
+    fn num_separate(parts: &[Vec<Segment>]) -> usize {
+        parts
+            .iter()
+            .flatten()
+            .filter(|segment| segment.text.split_whitespace().next().is_some())
+            .count()
+    }
+
+    /// Most subtasks the input may be split into, if there is a limit
+    fn subtask_limit(&self) -> Option<usize> {
+        let limit = match self.chunking {
+            Chunking::Subtasks(num_subtasks) => Some(num_subtasks),
+            _ => None,
+        };
+
+        match (limit, self.max_subtasks) {
+            (Some(limit), Some(max_subtasks)) => Some(limit.min(max_subtasks)),
+            (limit, max_subtasks) => limit.or(max_subtasks),
+        }
+    }
+
+    /// Works out how many chunks to split `segments` into
+    fn num_chunks(&self, segments: &[Segment], word_count: usize) -> usize {
+        let num_chunks = match self.chunking {
+            Chunking::Subtasks(num_subtasks) => num_subtasks,
+            Chunking::Words(num_words) => word_count.div_ceil(num_words),
+            Chunking::Duration(duration) => {
+                let total: Duration = segments
+                    .iter()
+                    .map(|segment| {
+                        segment
+                            .flite
+                            .as_ref()
+                            .unwrap_or(&self.flite)
+                            .estimate_duration(segment.text.split_whitespace().count())
+                    })
+                    .sum();
+                (total.as_secs_f64() / duration.as_secs_f64()).ceil() as usize
+            }
+        };
+        let num_chunks = match self.max_subtasks {
+            Some(max_subtasks) => num_chunks.min(max_subtasks),
+            None => num_chunks,
+        };
+
+        // every chunk needs at least a word, so tiny inputs get fewer chunks
+        if num_chunks > word_count {
+            log::info!(
+                "Splitting input of {} words into {} subtasks instead of {}",
+                word_count,
+                word_count,
+                num_chunks
+            );
+        }
+
+        num_chunks.clamp(1, word_count)
+    }
+
+    /// Builds the task synthesizing each of the chunks, with the words
+    /// respelled and the lexicon addenda attached as the lexicon says
+    pub fn task(&self, chunks: &[Chunk]) -> Task {
+        Task {
+            subtasks: chunks
+                .iter()
+                .map(|chunk| {
+                    let text = self.lexicon.respell(&chunk.text);
+                    let flite = chunk.flite.as_ref().unwrap_or(&self.flite);
+                    Subtask {
+                        lexicon: self.lexicon.addenda(&text),
+                        text,
+                        args: flite.args(),
+                        volume: flite.volume_percent(),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Estimates the length of the audio synthesized for `chunk`, excluding
+    /// the pause after it
+    pub fn estimate_duration(&self, chunk: &Chunk) -> Duration {
+        chunk
+            .flite
+            .as_ref()
+            .unwrap_or(&self.flite)
+            .estimate_duration(chunk.num_words)
+    }
+
+    /// Works out the silence to insert after each chunk
+    pub fn pauses(&self, chunks: &[Chunk]) -> Vec<Duration> {
+        chunks
+            .iter()
+            .map(|chunk| match chunk.pause {
+                Some(pause) => pause,
+                None if chunk.ends_paragraph() => self.paragraph_pause,
+                None => self.chunk_pause,
+            })
+            .collect()
+    }
+
+    /// Computes the task on the backend, passing the output of each subtask
+    /// to `on_output` along with its index as soon as it is available
+    ///
+    /// Identical subtasks are only computed once, and not at all if they
+    /// are in the cache. Subtasks which fail are resubmitted as a new,
+    /// smaller task, up to the configured number of retries; `on_retry` is
+    /// told the number of failed subtasks and the attempt before each
+    /// resubmission.
+    pub fn compute<R, F>(&self, task: Task, on_retry: R, on_output: F) -> Result<()>
+    where
+        R: FnMut(usize, u32),
+        F: FnMut(usize, Vec<u8>) -> Result<()>,
+    {
+        let result = self.compute_uncached(task, on_retry, on_output);
+
+        if let Some(cache) = &self.cache {
+            if let Err(e) = cache.prune() {
+                log::warn!("Pruning the cache failed: {:#}", e);
+            }
+        }
+
+        result
+    }
+
+    fn compute_uncached<R, F>(&self, task: Task, mut on_retry: R, mut on_output: F) -> Result<()>
+    where
+        R: FnMut(usize, u32),
+        F: FnMut(usize, Vec<u8>) -> Result<()>,
+    {
+        // identical chunks, such as repeated headings or boilerplate, are
+        // computed just once and their output handed out to each of them
+        let mut unique: Vec<&Subtask> = Vec::new();
+        let mut copies: Vec<Vec<usize>> = Vec::new();
+        let mut seen = HashMap::new();
+        for (i, subtask) in task.subtasks.iter().enumerate() {
+            let u = *seen.entry(subtask).or_insert_with(|| {
+                unique.push(subtask);
+                copies.push(Vec::new());
+                unique.len() - 1
+            });
+            copies[u].push(i);
+        }
+        if unique.len() < task.subtasks.len() {
+            log::info!(
+                "Computing {} duplicate chunks just once",
+                task.subtasks.len() - unique.len()
+            );
+        }
+
+        let mut hand_out = |u: usize, output: Vec<u8>| -> Result<()> {
+            let (&last, rest) = copies[u].split_last().unwrap();
+            for &i in rest {
+                on_output(i, output.clone())?;
+            }
+            on_output(last, output)
+        };
+
+        let mut pending: Vec<usize> = Vec::with_capacity(unique.len());
+        for (u, subtask) in unique.iter().enumerate() {
+            let cached = match &self.cache {
+                Some(cache) => cache.get(subtask).unwrap_or_else(|e| {
+                    log::warn!("Reading chunk from cache failed: {:#}", e);
+                    None
+                }),
+                None => None,
+            };
+            match cached {
+                Some(output) => hand_out(u, output)?,
+                None => pending.push(u),
+            }
+        }
+        if pending.len() < unique.len() {
+            log::info!(
+                "Taking {} chunks from the cache",
+                unique.len() - pending.len()
+            );
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let backend = self
+            .backend()
+            .ok_or_else(|| anyhow!("no backend set to compute the subtasks on"))?;
+        let _guard = self.cancel.guard();
+        let mut last_error = None;
+
+        for attempt in 0..=self.max_retries {
+            self.cancel.check()?;
+
+            if pending.is_empty() {
+                return Ok(());
+            }
+
+            if attempt > 0 {
+                on_retry(pending.len(), attempt);
+            }
+
+            let subtasks = pending.iter().map(|&u| unique[u].clone()).collect();
+            let progress_updater = if self.show_progress {
+                ProgressUpdater::new(pending.len() as u64)
+            } else {
+                ProgressUpdater::hidden(pending.len() as u64)
+            };
+
+            let outputs = match backend.compute(Task { subtasks }, progress_updater, &self.cancel) {
+                Ok(outputs) => outputs,
+                Err(e) if self.cancel.is_cancelled() => return Err(e),
+                Err(e) => {
+                    log::warn!("Computing task failed: {:#}", e);
+                    last_error = Some(e);
+                    continue;
+                }
+            };
+
+            let mut failed = Vec::new();
+            // subtasks missing from the outputs altogether count as failed too
+            let mut outputs = outputs.into_iter();
+            for &u in &pending {
+                let output = outputs
+                    .next()
+                    .unwrap_or_else(|| Err(anyhow!("subtask output missing")));
+                match output.and_then(|output| self.accept_output(unique[u], output)) {
+                    Ok(output) => hand_out(u, output)?,
+                    Err(e) => {
+                        log::warn!("Computing chunk {} failed: {:#}", copies[u][0], e);
+                        last_error = Some(e);
+                        failed.push(u);
+                    }
+                }
+            }
+            pending = failed;
+        }
+
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        bail!(
+            "{} of {} chunks failed after {} retries; last error: {:#}",
+            pending.iter().map(|&u| copies[u].len()).sum::<usize>(),
+            task.subtasks.len(),
+            self.max_retries,
+            last_error.unwrap_or_else(|| anyhow!("subtask output missing")),
+        )
+    }
+
+    /// Checks the output the backend computed for `subtask` and scales it
+    /// to its volume, keeping the result in the cache; for outputs collected
+    /// outside of [`Synthesizer::compute`], such as those of a task
+    /// submitted to Golem earlier
+    pub fn accept_output(&self, subtask: &Subtask, output: Vec<u8>) -> Result<Vec<u8>> {
+        let output = apply_volume(output, subtask.volume)?;
+        if let Some(cache) = &self.cache {
+            // a chunk missing from the cache is merely synthesized again
+            if let Err(e) = cache.put(subtask, &output) {
+                log::warn!("Caching chunk failed: {:#}", e);
+            }
+        }
+
+        Ok(output)
+    }
+
+    fn compute_all(&self, text: &str) -> Result<(Vec<Vec<u8>>, Vec<Duration>)> {
+        let chunks = self.split(text)?;
+        let mut outputs = vec![Vec::new(); chunks.len()];
+        self.compute(
+            self.task(&chunks),
+            |_, _| {},
+            |i, output| {
+                outputs[i] = output;
+                Ok(())
+            },
+        )?;
+
+        Ok((outputs, self.pauses(&chunks)))
+    }
+
+    /// Synthesizes `text` into samples held in memory
+    pub fn synthesize(&self, text: &str) -> Result<Audio> {
+        let (outputs, pauses) = self.compute_all(text)?;
+        let mut samples = Vec::new();
+        let combined = combine(
+            outputs.into_iter().map(Cursor::new),
+            &pauses,
+            &self.seams,
+            |_| Ok(Box::new(Collect(&mut samples))),
+        )?;
+
+        Ok(Audio {
+            sample_rate: combined.map_or(0, |combined| combined.sample_rate),
+            samples,
+        })
+    }
+
+    /// Synthesizes `text`, writing it into `writer` encoded in the given
+    /// format; returns the sample rate of the written audio
+    pub fn synthesize_to<W: Write + Seek>(
+        &self,
+        text: &str,
+        format: OutputFormat,
+        writer: W,
+    ) -> Result<u32> {
+        let (outputs, pauses) = self.compute_all(text)?;
+        let combined = combine(
+            outputs.into_iter().map(Cursor::new),
+            &pauses,
+            &self.seams,
+            |sample_rate| output::encoder(format, writer, sample_rate),
+        )?;
+
+        combined
+            .map(|combined| combined.sample_rate)
+            .ok_or_else(|| anyhow!("no audio synthesized"))
+    }
+
+    /// Same as [`Synthesizer::synthesize_to`], but for writers which can't
+    /// seek, such as pipes; see [`output::stream`]
+    pub fn stream_to<W: Write>(&self, text: &str, format: OutputFormat, writer: W) -> Result<u32> {
+        let (outputs, pauses) = self.compute_all(text)?;
+        let combined = combine(
+            outputs.into_iter().map(Cursor::new),
+            &pauses,
+            &self.seams,
+            |sample_rate| output::stream(format, writer, sample_rate),
+        )?;
+
+        combined
+            .map(|combined| combined.sample_rate)
+            .ok_or_else(|| anyhow!("no audio synthesized"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backend::Mock;
+
+    fn synthesizer(chunking: Chunking, max_subtasks: Option<usize>) -> Synthesizer {
+        let mut builder = Synthesizer::builder()
+            .backend(Box::new(Mock))
+            .chunking(chunking);
+        if let Some(max_subtasks) = max_subtasks {
+            builder = builder.max_subtasks(max_subtasks);
+        }
+        builder.build().unwrap()
+    }
+
+    fn ssml_with_breaks(num_breaks: usize) -> String {
+        let sentence = "One two three four five six seven eight nine ten. ";
+        let body = vec![sentence; num_breaks + 1].join(r#"<break time="1s"/>"#);
+        format!("<speak>{}</speak>", body)
+    }
+
+    #[test]
+    fn parts_share_the_subtasks() {
+        let synth = synthesizer(Chunking::Subtasks(5), None);
+        let parts = synth
+            .split_parts(
+                &[
+                    "One two three. Four five six.",
+                    "Seven eight.",
+                    "Nine ten eleven twelve.",
+                ],
+                InputFormat::Text,
+            )
+            .unwrap();
+
+        assert_eq!(parts.iter().map(Vec::len).sum::<usize>(), 5);
+        assert!(parts.iter().all(|part| !part.is_empty()));
+    }
+
+    #[test]
+    fn segments_share_the_subtasks() {
+        let synth = synthesizer(Chunking::Subtasks(6), None);
+        let chunks = synth
+            .split_as(&ssml_with_breaks(3), InputFormat::Ssml)
+            .unwrap();
+        assert_eq!(chunks.len(), 6);
+
+        let synth = synthesizer(Chunking::Words(2), Some(5));
+        let chunks = synth
+            .split_as(&ssml_with_breaks(3), InputFormat::Ssml)
+            .unwrap();
+        assert_eq!(chunks.len(), 5);
+    }
+
+    #[test]
+    fn pauses_after_every_paragraph() {
+        let text = "One two three.\n\nFour five.\n\nSix seven eight nine.";
+        let synth = |pause| {
+            Synthesizer::builder()
+                .backend(Box::new(Mock))
+                .subtasks(1)
+                .paragraph_pause(pause)
+                .build()
+                .unwrap()
+        };
+        let pause = Duration::from_millis(500);
+        let chunks = synth(pause).split(text).unwrap();
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(synth(pause).pauses(&chunks), [pause; 3]);
+
+        // the pauses are in the audio, bar the one at the very end
+        let with_pauses = synth(pause).synthesize(text).unwrap().samples.len();
+        let without = synth(Duration::from_millis(0))
+            .synthesize(text)
+            .unwrap()
+            .samples
+            .len();
+        assert_eq!(with_pauses - without, 2 * audio::num_samples(pause, 16000));
+
+        // past the cap, paragraphs share chunks again, but headings keep
+        // theirs as long as they fit
+        let synth = synthesizer(Chunking::Subtasks(1), Some(2));
+        let chunks = synth.split(text).unwrap();
+        assert_eq!(chunks.len(), 1);
+
+        let markdown = "# Heading\nOne two.\n\nThree four.\n\nFive six.\n";
+        let chunks = synth.split_as(markdown, InputFormat::Markdown).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].text, "Heading.");
+        assert!(chunks[0].ends_paragraph());
+    }
+
+    #[test]
+    fn takes_cached_chunks() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = Cache::new(dir.path()).unwrap();
+        let synth = Synthesizer::builder()
+            .backend(Box::new(Mock))
+            .cache(cache.clone())
+            .subtasks(2)
+            .build()
+            .unwrap();
+        let text = "One two three. Four five six.";
+        let audio = synth.synthesize(text).unwrap();
+
+        // every chunk is in the cache now, so no backend is needed
+        let synth = Synthesizer::builder()
+            .cache(cache)
+            .subtasks(2)
+            .build()
+            .unwrap();
+        assert_eq!(synth.synthesize(text).unwrap().samples, audio.samples);
+        assert!(synth.synthesize("Seven eight nine.").is_err());
+    }
+
+    #[test]
+    fn scales_the_output_to_the_volume() {
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut output = Vec::new();
+        let mut writer = hound::WavWriter::new(Cursor::new(&mut output), spec).unwrap();
+        for &sample in &[1000i16, -1000, 30000] {
+            writer.write_sample(sample).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let samples = |output: Vec<u8>| {
+            hound::WavReader::new(Cursor::new(output))
+                .unwrap()
+                .into_samples::<i16>()
+                .collect::<hound::Result<Vec<_>>>()
+                .unwrap()
+        };
+        assert_eq!(
+            samples(apply_volume(output.clone(), None).unwrap()),
+            [1000, -1000, 30000]
+        );
+        assert_eq!(
+            samples(apply_volume(output, Some(200)).unwrap()),
+            [2000, -2000, i16::MAX]
+        );
+        assert!(apply_volume(b"not audio".to_vec(), Some(50)).is_err());
+    }
+
+    #[test]
+    fn too_many_segments_for_the_subtasks() {
+        let synth = synthesizer(Chunking::Subtasks(4), None);
+        assert!(synth
+            .split_as(&ssml_with_breaks(5), InputFormat::Ssml)
+            .is_err());
+
+        let synth = synthesizer(Chunking::Words(100), Some(3));
+        assert!(synth
+            .split_as(&ssml_with_breaks(5), InputFormat::Ssml)
+            .is_err());
+
+        // without a limit, each segment simply gets a chunk of its own
+        let synth = synthesizer(Chunking::Words(100), None);
+        let chunks = synth
+            .split_as(&ssml_with_breaks(5), InputFormat::Ssml)
+            .unwrap();
+        assert_eq!(chunks.len(), 6);
+    }
+}